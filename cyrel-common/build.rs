@@ -0,0 +1,4 @@
+fn main() {
+    // Embed the new migrations, `sqlx::migrate!` doesn't track the directory
+    println!("cargo:rerun-if-changed=../migrations");
+}
@@ -0,0 +1,4 @@
+//! Code shared by the server and the sync binaries
+
+pub mod schema;
+pub mod time;
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::PgPool;
+use tracing::info;
+
+/// Migrations of the `migrations` directory, embedded at build time
+pub static MIGRATOR: Migrator = sqlx::migrate!("../migrations");
+
+/// Versions of the embedded migrations not yet applied to the database.
+///
+/// Fails if the database is dirty, or if an applied migration is unknown or was modified.
+pub async fn pending(pool: &PgPool) -> anyhow::Result<Vec<i64>> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+
+    if let Some(version) = conn.dirty_version().await? {
+        bail!(MigrateError::Dirty(version));
+    }
+
+    let applied: HashMap<_, _> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| (m.version, m.checksum))
+        .collect();
+
+    for version in applied.keys() {
+        if !MIGRATOR.iter().any(|m| m.version == *version) {
+            bail!(MigrateError::VersionMissing(*version));
+        }
+    }
+
+    let mut pending = Vec::new();
+    for migration in MIGRATOR.iter() {
+        match applied.get(&migration.version) {
+            Some(checksum) if *checksum != migration.checksum => {
+                bail!(MigrateError::VersionMismatch(migration.version))
+            }
+            Some(_) => {}
+            None => pending.push(migration.version),
+        }
+    }
+
+    Ok(pending)
+}
+
+/// Apply the pending migrations if `migrate`, then make sure the database has the expected
+/// schema.
+pub async fn ensure(pool: &PgPool, migrate: bool) -> anyhow::Result<()> {
+    if migrate {
+        MIGRATOR.run(pool).await?;
+        info!("database migrated");
+    }
+
+    let pending = pending(pool).await?;
+    if !pending.is_empty() {
+        bail!(
+            "the database schema is outdated, {} migrations are pending ({:?}): run with --migrate",
+            pending.len(),
+            pending
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// Convert a local time of `tz` to UTC.
+///
+/// Ambiguous times, when clocks go back, are the earliest ones. Skipped times, when clocks go
+/// forward, are moved an hour later, failing if that time is skipped too.
+pub fn localize(tz: Tz, time: NaiveDateTime) -> anyhow::Result<DateTime<Utc>> {
+    tz.from_local_datetime(&time)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(time + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| anyhow!("{} doesn't exist in {}", time, tz))
+}
@@ -1,32 +1,20 @@
 use std::env;
 use std::sync::Arc;
-use std::{collections::HashMap, time::Duration};
 
 use anyhow::{anyhow, Context};
-use celcat::{
-    entities::{Student, StudentId},
-    fetch::Celcat,
-    fetchable::{
-        calendar::{CalView, CalendarData, CalendarDataRequest, Course},
-        event::{Element, Event, EventRequest, RawElement},
-    },
-};
-use chrono::naive::NaiveDate;
+use chrono::Local;
+use clap::{clap_app, crate_authors, crate_version};
+use cyrel_common::schema;
+use cyrel_sync::client::{self, Limiter};
+use cyrel_sync::courses::{self, Window};
+use cyrel_sync::history::Run;
+use cyrel_sync::job::{self, Job};
 use dotenv::dotenv;
-use futures::future::{join_all, try_join_all};
+use serde_json::json;
 use sqlx::postgres::PgPool;
-use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
-use tokio_retry::{strategy::ExponentialBackoff, Retry};
-use tracing::{error, warn};
+use tracing::info;
 use tracing_subscriber::EnvFilter;
 
-struct State {
-    pool: PgPool,
-    celcat: Celcat,
-}
-
-type Message = (Course, oneshot::Sender<()>);
-
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let _ = dotenv();
@@ -36,265 +24,79 @@ async fn main() -> anyhow::Result<()> {
         .try_init()
         .map_err(|e| anyhow!(e))?;
 
-    let pool = PgPool::connect(&env::var("DATABASE_URL")?)
-        .await
-        .context("Failed to connect to PostgreSQL")?;
-    let celcat = {
-        let mut c = Celcat::new("https://services-web.u-cergy.fr/calendar")
-            .await
-            .context("Failed to connect to Celcat")?;
-        c.login(&env::var("CELCAT_USERNAME")?, &env::var("CELCAT_PASSWORD")?)
-            .await
-            .context("Failed to login to Celcat")?;
-        c
-    };
-
-    let state: &_ = Box::leak(Box::new(State { pool, celcat }));
-
-    let gr = get_group_referents(&state.pool)
-        .await
-        .context("Failed to get groups referents")?;
-
-    let (tx, rx) = mpsc::channel(100);
-    let tx_ref = &tx;
-
-    let handle = tokio::spawn(async move { event_updater(state, rx).await });
-
-    join_all(gr.into_iter().map(|(g, r)| async move {
-        if let Err(err) = update_courses(&state, g, r, tx_ref.clone()).await {
-            error!("Failed to update courses: {}", err);
-        }
-    }))
-    .await;
-
-    drop(tx);
-    handle.await?;
-
-    Ok(())
-}
-
-async fn get_group_referents(pool: &PgPool) -> anyhow::Result<Vec<(i32, StudentId)>> {
-    let referents = sqlx::query!(
-        r#"
-SELECT id, referent
-FROM groups
-WHERE referent IS NOT NULL
-        "#
-    )
-    .fetch_all(pool)
-    .await?;
-
-    Ok(referents
-        .into_iter()
-        .map(|r| {
-            (
-                r.id,
-                StudentId(
-                    r.referent
-                        .expect("the query should not return any null values")
-                        .to_string(),
-                ),
-            )
-        })
-        .collect())
-}
-
-async fn update_courses(
-    state: &State,
-    group: i32,
-    referent: StudentId,
-    s: mpsc::Sender<Message>,
-) -> anyhow::Result<()> {
-    let calendar: CalendarData<Student> = state
-        .celcat
-        .fetch(CalendarDataRequest {
-            start: NaiveDate::from_ymd(2021, 9, 1).and_hms(0, 0, 0),
-            end: NaiveDate::from_ymd(2022, 9, 1).and_hms(0, 0, 0),
-            res_type: Student,
-            cal_view: CalView::Month,
-            federation_ids: referent,
-            colour_scheme: 3,
-        })
-        .await?;
-
-    let mut tx = state.pool.begin().await?;
-
-    sqlx::query!(
-        r#"
-DELETE FROM groups_courses
-WHERE group_id = $1
-        "#,
-        group
+    let matches = clap_app!(
+        cyrel_sync_courses =>
+            (version: crate_version!())
+            (author: crate_authors!())
+            (about: "Sync the courses of every group from Celcat")
+            (@arg START: --start +takes_value "first day to sync (YYYY-MM-DD)")
+            (@arg END: --end +takes_value "day after the last day to sync (YYYY-MM-DD)")
+            (@arg ROLLOVER: --rollover +takes_value "first day of the academic year (MM-DD), 09-01 by default")
+            (@arg WEEKS_BACK: --("weeks-back") +takes_value "number of past weeks to sync")
+            (@arg WEEKS_AHEAD: --("weeks-ahead") +takes_value "number of future weeks to sync")
+            (@arg TIMEZONE: --timezone +takes_value "timezone of the campus, Europe/Paris by default")
+            (@arg EVENT_MAX_AGE: --("event-max-age") +takes_value "seconds after which unchanged side bar events are fetched again, 86400 by default")
+            (@arg CELCAT_URL: --("celcat-url") +takes_value "address of Celcat, https://services-web.u-cergy.fr/calendar by default")
+            (@arg CELCAT_CONCURRENCY: --("celcat-concurrency") +takes_value "requests sent to Celcat at the same time, 4 by default")
+            (@arg CELCAT_RATE: --("celcat-rate") +takes_value "requests sent to Celcat per second, 10 by default")
+            (@arg DRY_RUN: --("dry-run") "fetch everything from Celcat but roll back the database, printing what would change")
+            (@arg JSON: --json requires[DRY_RUN] "print what would change as JSON")
+            (@arg MIGRATE: --migrate "apply the pending database migrations before syncing")
+            (@arg CHECK_SCHEMA: --("check-schema") "check the database schema and exit")
     )
-    .execute(&mut tx)
-    .await?;
+    .get_matches();
 
-    let tx = Mutex::new(tx);
+    let window = Window::from_args(&matches)?;
+    let (start, end) = window.range(Local::now().date_naive())?;
+    info!("syncing {:?}: from {} to {}", window, start, end);
 
-    try_join_all(
-        calendar
-            .courses
-            .iter()
-            .map(|c| update_course(&tx, group, c, s.clone())),
-    )
-    .await
-    .with_context(|| format!("Failed to update courses for group {}", group))?;
-
-    tx.into_inner().commit().await?;
+    let timezone = courses::timezone(&matches)?;
+    let event_max_age = courses::event_max_age(&matches)?;
+    let limiter = Limiter::from_args(&matches)?;
 
-    Ok(())
-}
-
-async fn update_course(
-    tx: &Mutex<sqlx::Transaction<'static, sqlx::Postgres>>,
-    group: i32,
-    course: &Course,
-    s: mpsc::Sender<Message>,
-) -> anyhow::Result<()> {
-    let (otx, orx) = oneshot::channel();
-    s.send((course.clone(), otx)).await?;
-    if let Err(_) = orx.await {
-        return Err(anyhow!("Failed to update side bar event"));
+    let pool = PgPool::connect(&env::var("DATABASE_URL")?)
+        .await
+        .context("Failed to connect to PostgreSQL")?;
+    schema::ensure(&pool, matches.is_present("MIGRATE")).await?;
+    if matches.is_present("CHECK_SCHEMA") {
+        info!("the database schema is up to date");
+        return Ok(());
     }
 
-    let mut tx = tx.lock().await;
-    sqlx::query!(
-        r#"
-INSERT INTO groups_courses (group_id, course_id)
-VALUES ( $1, $2 )
-        "#,
-        group,
-        course.id.0
-    )
-    .execute(&mut *tx)
-    .await?;
-
-    Ok(())
-}
-
-async fn event_updater(state: &'static State, mut rx: mpsc::Receiver<Message>) {
-    let mut already_updated = HashMap::<String, Arc<RwLock<()>>>::new();
+    let lock = job::try_lock(&pool, Job::Courses)
+        .await?
+        .ok_or_else(|| anyhow!("Another sync of the courses is running"))?;
 
-    while let Some((c, s)) = rx.recv().await {
-        if let Some(lock) = already_updated.get(&c.id.0) {
-            let lock = Arc::clone(lock);
-            tokio::spawn(async move {
-                lock.read().await;
-                if let Err(_) = s.send(()) {
-                    warn!("The receiver dropped");
-                }
-            });
-        } else {
-            let lock = Arc::new(RwLock::new(()));
-            let pending = Arc::clone(&lock).write_owned().await;
-            already_updated.insert(c.id.0.clone(), lock);
-
-            tokio::spawn(async move {
-                let strategy =
-                    ExponentialBackoff::from_millis(100).max_delay(Duration::from_secs(60));
-
-                let updated = Retry::spawn(strategy, || update_event(state, c.clone())).await;
-                if let Err(err) = updated {
-                    // BUG: course id is in `already_updated` but we failed to update it.
-                    error!("Failed to update side bar event: {}", err);
-                    return;
-                }
-                drop(pending);
-                if let Err(_) = s.send(()) {
-                    warn!("The receiver dropped");
-                }
-            });
-        }
-    }
-}
-
-async fn update_event(state: &State, course: Course) -> anyhow::Result<()> {
-    let event: Event = match state
-        .celcat
-        .fetch(EventRequest {
-            event_id: course.id.clone(),
-        })
-        .await
-    {
-        Ok(event) => event,
-        Err(err) => {
-            error!(
-                "Failed to fetch side bar event for course {}: {}",
-                course.id.0, err
-            );
-            return Ok(());
-        }
+    let dry_run = matches.is_present("DRY_RUN");
+    let run = if dry_run {
+        None
+    } else {
+        Some(Run::start(&pool, Job::Courses).await?)
     };
 
-    let mut category: Option<String> = None;
-    let mut module: Option<String> = None;
-    let mut room: Option<String> = None;
-    let mut teacher: Option<String> = None;
-    let mut description: Option<String> = None;
-
-    for e in event.elements.0 {
-        use Element::*;
-        match e {
-            Category(RawElement { content, .. }) => {
-                category = content;
-            }
-            Module(RawElement { content, .. }) => {
-                module = content;
-            }
-            Room(RawElement { content, .. }) => {
-                room = content;
-            }
-            Teacher(RawElement { content, .. }) => {
-                teacher = content;
-            }
-            Name(RawElement { content, .. }) => {
-                description = content;
-            }
-            _ => {}
-        }
+    let result = async {
+        let celcat = client::connect(matches.value_of("CELCAT_URL"), Arc::new(limiter)).await?;
+        courses::sync(&pool, celcat, start, end, timezone, event_max_age, dry_run).await
     }
+    .await;
 
-    sqlx::query!(
-        r#"
-INSERT INTO courses
-    ( id
-    , start_time
-    , end_time
-    , category
-    , module
-    , room
-    , teacher
-    , description
-    )
-VALUES ( $1, $2, $3, $4, $5, $6, $7, $8 )
-ON CONFLICT (id) DO UPDATE
-SET ( start_time
-    , end_time
-    , category
-    , module
-    , room
-    , teacher
-    , description
-    ) = ( EXCLUDED.start_time
-        , EXCLUDED.end_time
-        , EXCLUDED.category
-        , EXCLUDED.module
-        , EXCLUDED.room
-        , EXCLUDED.teacher
-        , EXCLUDED.description
-        )
-        "#,
-        course.id.0,
-        course.start,
-        course.end,
-        category,
-        module,
-        room,
-        teacher,
-        description
-    )
-    .execute(&state.pool)
-    .await?;
+    if let Some(run) = run {
+        run.finish_courses(&pool, &result).await?;
+    }
+    lock.release().await?;
+
+    let (summary, report) = result?;
+
+    if matches.is_present("JSON") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json!({ "summary": summary, "report": report }))?
+        );
+    } else if dry_run {
+        println!("{}\n{}", report, summary);
+    } else {
+        println!("{}", summary);
+    }
 
     Ok(())
 }
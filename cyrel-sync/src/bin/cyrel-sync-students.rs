@@ -1,13 +1,16 @@
 use std::env;
+use std::sync::Arc;
 
 use anyhow::{anyhow, Context};
-use celcat::{
-    entities::Student,
-    fetch::Celcat,
-    fetchable::resources::{ResourceList, ResourceListRequest},
-};
+use clap::{clap_app, crate_authors, crate_version};
+use cyrel_common::schema;
+use cyrel_sync::client::{self, Limiter};
+use cyrel_sync::history::Run;
+use cyrel_sync::job::{self, Job};
+use cyrel_sync::students;
 use dotenv::dotenv;
 use sqlx::postgres::PgPool;
+use tracing::info;
 use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
@@ -19,69 +22,61 @@ async fn main() -> anyhow::Result<()> {
         .try_init()
         .map_err(|e| anyhow!(e))?;
 
+    let matches = clap_app!(
+        cyrel_sync_students =>
+            (version: crate_version!())
+            (author: crate_authors!())
+            (about: "Sync the students of every department from Celcat")
+            (@arg CELCAT_URL: --("celcat-url") +takes_value "address of Celcat, https://services-web.u-cergy.fr/calendar by default")
+            (@arg CELCAT_CONCURRENCY: --("celcat-concurrency") +takes_value "requests sent to Celcat at the same time, 4 by default")
+            (@arg CELCAT_RATE: --("celcat-rate") +takes_value "requests sent to Celcat per second, 10 by default")
+            (@arg DRY_RUN: --("dry-run") "fetch everything from Celcat but roll back the database, printing what would change")
+            (@arg JSON: --json requires[DRY_RUN] "print what would change as JSON")
+            (@arg MIGRATE: --migrate "apply the pending database migrations before syncing")
+            (@arg CHECK_SCHEMA: --("check-schema") "check the database schema and exit")
+    )
+    .get_matches();
+
+    let limiter = Limiter::from_args(&matches)?;
+
     let pool = PgPool::connect(&env::var("DATABASE_URL")?)
         .await
         .context("Failed to connect to PostgreSQL")?;
-    let celcat = {
-        let mut c = Celcat::new("https://services-web.u-cergy.fr/calendar")
-            .await
-            .context("Failed to connect to Celcat")?;
-        c.login(&env::var("CELCAT_USERNAME")?, &env::var("CELCAT_PASSWORD")?)
-            .await
-            .context("Failed to login to Celcat")?;
-        c
-    };
+    schema::ensure(&pool, matches.is_present("MIGRATE")).await?;
+    if matches.is_present("CHECK_SCHEMA") {
+        info!("the database schema is up to date");
+        return Ok(());
+    }
+
+    let lock = job::try_lock(&pool, Job::Students)
+        .await?
+        .ok_or_else(|| anyhow!("Another sync of the students is running"))?;
 
-    let students: ResourceList<Student> = celcat
-        .fetch(ResourceListRequest {
-            my_resources: false,
-            search_term: "__".to_owned(),
-            page_size: 1000000,
-            page_number: 0,
-            res_type: Student,
-        })
-        .await?;
+    let dry_run = matches.is_present("DRY_RUN");
+    let run = if dry_run {
+        None
+    } else {
+        Some(Run::start(&pool, Job::Students).await?)
+    };
 
-    let mut tx = pool.begin().await?;
-    for s in students.results {
-        let (firstname, lastname) = separate_names(&s.text)?;
-        sqlx::query!(
-            r#"
-INSERT INTO celcat_students (id, firstname, lastname, department)
-VALUES ( $1, $2, $3, $4 )
-ON CONFLICT (id) DO UPDATE
-SET (firstname, lastname, department) = (EXCLUDED.firstname, EXCLUDED.lastname, EXCLUDED.department)
-            "#,
-            s.id.0.parse::<i64>()?,
-            firstname,
-            lastname,
-            s.dept
-        )
-        .execute(&mut tx)
-        .await?;
+    let result = async {
+        let celcat = client::connect(matches.value_of("CELCAT_URL"), Arc::new(limiter)).await?;
+        students::sync(&pool, &celcat, dry_run).await
     }
-    tx.commit().await?;
+    .await;
 
-    Ok(())
-}
+    if let Some(run) = run {
+        run.finish_students(&pool, &result).await?;
+    }
+    lock.release().await?;
 
-fn separate_names(name: &str) -> anyhow::Result<(String, String)> {
-    let name: String = name
-        .split_inclusive(|c: char| !c.is_alphabetic())
-        .map(|w| {
-            let mut cs = w.chars();
-            match cs.next() {
-                Some(c) => c.to_string() + &cs.as_str().to_lowercase(),
-                None => String::new(),
-            }
-        })
-        .collect();
+    let report = result?;
 
-    match name.rsplit_once(' ') {
-        Some((l, f)) => Ok((f.to_owned(), l.to_owned())),
-        _ => Err(anyhow!(
-            "Can't split '{}' into firstname and lastname",
-            name
-        )),
+    if matches.is_present("JSON") {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else if dry_run {
+        println!("{}", report);
     }
+
+    Ok(())
 }
@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Duration as ChronoDuration, Local, Utc};
+use chrono_tz::Tz;
+use clap::{clap_app, crate_authors, crate_version};
+use cyrel_common::schema;
+use cyrel_sync::client::{self, Limiter};
+use cyrel_sync::courses::{self, Window};
+use cyrel_sync::history::Run;
+use cyrel_sync::job::{self, Job};
+use cyrel_sync::{arg, students};
+use dotenv::dotenv;
+use futures::future::try_join_all;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response, Server};
+use serde::Serialize;
+use sqlx::postgres::PgPool;
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+struct Config {
+    /// Window of the courses job
+    window: Window,
+    /// The near courses job syncs from today to this number of weeks ahead
+    near_weeks: i64,
+    timezone: Tz,
+    event_max_age: ChronoDuration,
+    celcat_url: Option<String>,
+    /// Shared by every job
+    limiter: Arc<Limiter>,
+}
+
+/// What happened the last time a job ran
+#[derive(Debug, Default, Serialize)]
+struct Status {
+    running: bool,
+    last_start: Option<DateTime<Utc>>,
+    last_end: Option<DateTime<Utc>>,
+    last_success: Option<DateTime<Utc>>,
+    /// Why the last run failed
+    last_error: Option<String>,
+    /// Runs skipped because another one held the lock
+    skipped: u64,
+}
+
+type Statuses = Arc<Mutex<BTreeMap<&'static str, Status>>>;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let _ = dotenv();
+
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .try_init()
+        .map_err(|e| anyhow!(e))?;
+
+    let matches = clap_app!(
+        cyrel_sync =>
+            (version: crate_version!())
+            (author: crate_authors!())
+            (about: "Sync the courses and the students from Celcat periodically")
+            (@arg START: --start +takes_value "first day to sync (YYYY-MM-DD)")
+            (@arg END: --end +takes_value "day after the last day to sync (YYYY-MM-DD)")
+            (@arg ROLLOVER: --rollover +takes_value "first day of the academic year (MM-DD), 09-01 by default")
+            (@arg WEEKS_BACK: --("weeks-back") +takes_value "number of past weeks to sync")
+            (@arg WEEKS_AHEAD: --("weeks-ahead") +takes_value "number of future weeks to sync")
+            (@arg NEAR_WEEKS: --("near-weeks") +takes_value "number of weeks synced by the near courses job, 2 by default")
+            (@arg COURSES_INTERVAL: --("courses-interval") +takes_value "seconds between two syncs of the courses, 3600 by default")
+            (@arg NEAR_INTERVAL: --("near-interval") +takes_value "seconds between two syncs of the near courses, 600 by default")
+            (@arg STUDENTS_INTERVAL: --("students-interval") +takes_value "seconds between two syncs of the students, 86400 by default")
+            (@arg STATUS_PORT: --("status-port") +takes_value "port serving the status of the jobs, none by default")
+            (@arg TIMEZONE: --timezone +takes_value "timezone of the campus, Europe/Paris by default")
+            (@arg EVENT_MAX_AGE: --("event-max-age") +takes_value "seconds after which unchanged side bar events are fetched again, 86400 by default")
+            (@arg CELCAT_URL: --("celcat-url") +takes_value "address of Celcat, https://services-web.u-cergy.fr/calendar by default")
+            (@arg CELCAT_CONCURRENCY: --("celcat-concurrency") +takes_value "requests sent to Celcat at the same time, 4 by default")
+            (@arg CELCAT_RATE: --("celcat-rate") +takes_value "requests sent to Celcat per second, 10 by default")
+            (@arg MIGRATE: --migrate "apply the pending database migrations before syncing")
+            (@arg CHECK_SCHEMA: --("check-schema") "check the database schema and exit")
+    )
+    .get_matches();
+
+    let number = |name: &str, var: &str, default: u64| -> anyhow::Result<u64> {
+        match arg(&matches, name, var) {
+            Some(n) => n
+                .parse()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| anyhow!("Invalid number for {}: {}", name, n)),
+            None => Ok(default),
+        }
+    };
+
+    let config = Arc::new(Config {
+        window: Window::from_args(&matches)?,
+        near_weeks: number("NEAR_WEEKS", "SYNC_NEAR_WEEKS", 2)? as i64,
+        timezone: courses::timezone(&matches)?,
+        event_max_age: courses::event_max_age(&matches)?,
+        celcat_url: arg(&matches, "CELCAT_URL", "CELCAT_URL"),
+        limiter: Arc::new(Limiter::from_args(&matches)?),
+    });
+    let schedule = [
+        (
+            Job::Courses,
+            number("COURSES_INTERVAL", "SYNC_COURSES_INTERVAL", 60 * 60)?,
+        ),
+        (
+            Job::NearCourses,
+            number("NEAR_INTERVAL", "SYNC_NEAR_INTERVAL", 10 * 60)?,
+        ),
+        (
+            Job::Students,
+            number("STUDENTS_INTERVAL", "SYNC_STUDENTS_INTERVAL", 24 * 60 * 60)?,
+        ),
+    ];
+    let status_port = arg(&matches, "STATUS_PORT", "SYNC_STATUS_PORT")
+        .map(|p| {
+            p.parse::<u16>()
+                .with_context(|| format!("Invalid port: {}", p))
+        })
+        .transpose()?;
+
+    let pool = PgPool::connect(&env::var("DATABASE_URL")?)
+        .await
+        .context("Failed to connect to PostgreSQL")?;
+    schema::ensure(&pool, matches.is_present("MIGRATE")).await?;
+    if matches.is_present("CHECK_SCHEMA") {
+        info!("the database schema is up to date");
+        return Ok(());
+    }
+
+    let statuses = Statuses::default();
+
+    if let Some(port) = status_port {
+        let statuses = Arc::clone(&statuses);
+        tokio::spawn(async move {
+            if let Err(err) = serve_status(port, statuses).await {
+                error!("Failed to serve the status: {}", err);
+            }
+        });
+    }
+
+    // The jobs run forever, unless one of them panics
+    try_join_all(schedule.into_iter().map(|(job, interval)| {
+        info!("syncing {} every {} seconds", job.as_str(), interval);
+        tokio::spawn(run_periodically(
+            Arc::clone(&config),
+            pool.clone(),
+            job,
+            Duration::from_secs(interval),
+            Arc::clone(&statuses),
+        ))
+    }))
+    .await
+    .context("A sync job panicked")?;
+
+    Ok(())
+}
+
+/// Run `job` forever, every `interval`, skipping the runs while another one holds its lock
+async fn run_periodically(
+    config: Arc<Config>,
+    pool: PgPool,
+    job: Job,
+    interval: Duration,
+    statuses: Statuses,
+) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let lock = match job::try_lock(&pool, job).await {
+            Ok(Some(lock)) => lock,
+            Ok(None) => {
+                info!("skipping {}, another sync is running", job.as_str());
+                let mut statuses = statuses.lock().unwrap();
+                statuses.entry(job.as_str()).or_default().skipped += 1;
+                continue;
+            }
+            Err(err) => {
+                error!("Failed to lock {}: {}", job.as_str(), err);
+                continue;
+            }
+        };
+
+        {
+            let mut statuses = statuses.lock().unwrap();
+            let status = statuses.entry(job.as_str()).or_default();
+            status.running = true;
+            status.last_start = Some(Utc::now());
+        }
+
+        info!("syncing {}", job.as_str());
+        let result = run(&config, &pool, job).await;
+
+        if let Err(err) = lock.release().await {
+            warn!("Failed to unlock {}: {}", job.as_str(), err);
+        }
+
+        let mut statuses = statuses.lock().unwrap();
+        let status = statuses.entry(job.as_str()).or_default();
+        let now = Utc::now();
+        status.running = false;
+        status.last_end = Some(now);
+        match result {
+            Ok(()) => {
+                info!("synced {}", job.as_str());
+                status.last_success = Some(now);
+                status.last_error = None;
+            }
+            Err(err) => {
+                error!("Failed to sync {}: {:#}", job.as_str(), err);
+                status.last_error = Some(format!("{:#}", err));
+            }
+        }
+    }
+}
+
+async fn run(config: &Config, pool: &PgPool, job: Job) -> anyhow::Result<()> {
+    let today = Local::now().date_naive();
+    let window = match job {
+        Job::Courses => config.window,
+        Job::NearCourses => Window::Rolling {
+            weeks_back: 0,
+            weeks_ahead: config.near_weeks,
+        },
+        Job::Students => {
+            let run = Run::start(pool, job).await?;
+            let result = async {
+                let celcat =
+                    client::connect(config.celcat_url.as_deref(), Arc::clone(&config.limiter))
+                        .await?;
+                students::sync(pool, &celcat, false).await
+            }
+            .await;
+            run.finish_students(pool, &result).await?;
+            result?;
+            return Ok(());
+        }
+    };
+
+    let (start, end) = window.range(today)?;
+    let run = Run::start(pool, job).await?;
+    let result = async {
+        let celcat =
+            client::connect(config.celcat_url.as_deref(), Arc::clone(&config.limiter)).await?;
+        courses::sync(
+            pool,
+            celcat,
+            start,
+            end,
+            config.timezone,
+            config.event_max_age,
+            false,
+        )
+        .await
+    }
+    .await;
+    run.finish_courses(pool, &result).await?;
+
+    let (summary, _) = result?;
+    info!("{}", summary);
+
+    Ok(())
+}
+
+/// Serve the status of every job as JSON, on localhost
+async fn serve_status(port: u16, statuses: Statuses) -> anyhow::Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let statuses = Arc::clone(&statuses);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |_| {
+                let body = serde_json::to_string(&*statuses.lock().unwrap());
+                async move {
+                    let body = body.map_err(|err| anyhow!(err))?;
+                    Ok::<_, anyhow::Error>(
+                        Response::builder()
+                            .header(CONTENT_TYPE, "application/json")
+                            .body(Body::from(body))?,
+                    )
+                }
+            }))
+        }
+    });
+
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
+    info!("serving the status on {}", addr);
+    Server::bind(&addr).serve(make_service).await?;
+
+    Ok(())
+}
@@ -0,0 +1,166 @@
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use celcat::fetch::Celcat;
+use celcat::fetchable::Fetchable;
+use clap::ArgMatches;
+use tokio::sync::Semaphore;
+use tokio::time::{self, Instant};
+use tracing::{debug, info, warn};
+
+use crate::arg;
+
+/// Celcat of CY Cergy Paris University
+pub const DEFAULT_URL: &str = "https://services-web.u-cergy.fr/calendar";
+
+/// Requests sent to Celcat at the same time, by default
+pub const DEFAULT_CONCURRENCY: usize = 4;
+/// Requests sent to Celcat per second, by default
+pub const DEFAULT_RATE: f64 = 10.0;
+
+/// Responses slower than this make the requests back off
+const SLOW: Duration = Duration::from_secs(5);
+/// Requests taking longer than this fail
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// Requests are never spaced more than this when Celcat keeps failing, unless the rate is lower
+const MAX_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Limits on the requests sent to Celcat, shared by the clients of a process.
+///
+/// Requests are spaced by at least the inverse of the rate. The spacing doubles each time Celcat
+/// fails or answers slowly, and shrinks back as it recovers.
+pub struct Limiter {
+    permits: Semaphore,
+    min_interval: Duration,
+    pace: Mutex<Pace>,
+}
+
+struct Pace {
+    /// Current spacing between two requests
+    interval: Duration,
+    /// When the next request may be sent
+    next: Instant,
+}
+
+impl Limiter {
+    /// Fails unless `concurrency` and `rate` are positive
+    pub fn new(concurrency: usize, rate: f64) -> anyhow::Result<Self> {
+        if concurrency == 0 {
+            bail!("Invalid number of concurrent requests: {}", concurrency);
+        }
+        let min_interval = Some(rate)
+            .filter(|r| r.is_finite())
+            .and_then(|r| Duration::try_from_secs_f64(1.0 / r).ok())
+            .ok_or_else(|| anyhow!("Invalid number of requests per second: {:?}", rate))?;
+        Ok(Self {
+            permits: Semaphore::new(concurrency),
+            min_interval,
+            pace: Mutex::new(Pace {
+                interval: min_interval,
+                next: Instant::now(),
+            }),
+        })
+    }
+
+    /// Every option can also be set by an environment variable
+    pub fn from_args(matches: &ArgMatches) -> anyhow::Result<Self> {
+        let concurrency = match arg(matches, "CELCAT_CONCURRENCY", "CELCAT_CONCURRENCY") {
+            Some(c) => c
+                .parse()
+                .map_err(|_| anyhow!("Invalid number of concurrent requests: {}", c))?,
+            None => DEFAULT_CONCURRENCY,
+        };
+        let rate = match arg(matches, "CELCAT_RATE", "CELCAT_RATE") {
+            Some(r) => r
+                .parse()
+                .map_err(|_| anyhow!("Invalid number of requests per second: {}", r))?,
+            None => DEFAULT_RATE,
+        };
+        let limiter = Self::new(concurrency, rate)?;
+        info!(
+            "sending at most {} requests at a time and {} per second to Celcat",
+            concurrency, rate
+        );
+
+        Ok(limiter)
+    }
+
+    /// Wait for the turn of a request
+    async fn wait(&self) -> tokio::sync::SemaphorePermit<'_> {
+        let permit = self
+            .permits
+            .acquire()
+            .await
+            .expect("the semaphore is never closed");
+
+        let at = {
+            let mut pace = self.pace.lock().unwrap();
+            let at = pace.next.max(Instant::now());
+            pace.next = at + pace.interval;
+            at
+        };
+        time::sleep_until(at).await;
+
+        permit
+    }
+
+    /// Adapt the spacing of the requests to how the last one went
+    fn report(&self, ok: bool, elapsed: Duration) {
+        let mut pace = self.pace.lock().unwrap();
+        if ok && elapsed < SLOW {
+            pace.interval = (pace.interval * 3 / 4).max(self.min_interval);
+        } else {
+            pace.interval = (pace.interval * 2).min(MAX_INTERVAL.max(self.min_interval));
+            warn!(
+                "Celcat is failing or slow, spacing the requests by {:?}",
+                pace.interval
+            );
+        }
+    }
+}
+
+/// A Celcat session whose requests are limited
+pub struct Client {
+    celcat: Celcat,
+    limiter: Arc<Limiter>,
+}
+
+impl Client {
+    pub async fn fetch<F>(&self, req: F::Request) -> anyhow::Result<F>
+    where
+        F: Fetchable,
+    {
+        let _permit = self.limiter.wait().await;
+
+        let start = Instant::now();
+        let res = time::timeout(TIMEOUT, self.celcat.fetch(req)).await;
+        let elapsed = start.elapsed();
+        debug!("{} answered in {:?}", F::METHOD_NAME, elapsed);
+
+        self.limiter.report(matches!(res, Ok(Ok(_))), elapsed);
+        match res {
+            Ok(res) => Ok(res?),
+            Err(_) => Err(anyhow!("Celcat didn't answer in {:?}", TIMEOUT)),
+        }
+    }
+}
+
+/// Connect to Celcat at `url`, `CELCAT_URL` or [`DEFAULT_URL`], and login with `CELCAT_USERNAME`
+/// and `CELCAT_PASSWORD`
+pub async fn connect(url: Option<&str>, limiter: Arc<Limiter>) -> anyhow::Result<Client> {
+    let url = match url {
+        Some(url) => url.to_owned(),
+        None => env::var("CELCAT_URL").unwrap_or_else(|_| DEFAULT_URL.to_owned()),
+    };
+    info!("connecting to Celcat at {}", url);
+
+    let mut c = Celcat::new(&url)
+        .await
+        .context("Failed to connect to Celcat")?;
+    c.login(&env::var("CELCAT_USERNAME")?, &env::var("CELCAT_PASSWORD")?)
+        .await
+        .context("Failed to login to Celcat")?;
+    Ok(Client { celcat: c, limiter })
+}
@@ -0,0 +1,1248 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use celcat::{
+    entities::{Student, StudentId},
+    fetchable::{
+        calendar::{CalView, CalendarData, CalendarDataRequest, Course},
+        event::{Element, Event, EventRequest, RawElement},
+    },
+};
+use chrono::naive::{NaiveDate, NaiveDateTime, NaiveTime};
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Utc};
+use chrono_tz::Tz;
+use clap::ArgMatches;
+use cyrel_common::time::localize;
+use futures::future::{join_all, try_join_all, BoxFuture, FutureExt, Shared};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::{PgConnection, PgPool, Postgres};
+use sqlx::{Connection, PgExecutor, Transaction};
+use tokio::sync::{mpsc, oneshot, Mutex, MutexGuard};
+use tokio_retry::strategy::{jitter, ExponentialBackoff};
+use tokio_retry::Retry;
+use tracing::{error, info, warn};
+
+use crate::arg;
+use crate::client::Client;
+
+struct State {
+    pool: PgPool,
+    celcat: Client,
+    counters: Counters,
+    /// Courses are synced between those
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    /// Celcat times are in the local time of this timezone
+    timezone: Tz,
+    /// Side bar events fetched more recently than this are only fetched again if their calendar
+    /// entry changed
+    event_max_age: ChronoDuration,
+    /// Transaction of a dry run, rolled back at the end, none otherwise
+    dry_run: Mutex<Option<Transaction<'static, Postgres>>>,
+    report: Mutex<Report>,
+}
+
+impl State {
+    /// A connection to write with, the transaction of the run if it's dry
+    async fn acquire(&self) -> sqlx::Result<Conn<'_>> {
+        let dry_run = self.dry_run.lock().await;
+        if dry_run.is_some() {
+            Ok(Conn::DryRun(dry_run))
+        } else {
+            Ok(Conn::Pooled(Box::new(self.pool.acquire().await?)))
+        }
+    }
+}
+
+enum Conn<'a> {
+    Pooled(Box<PoolConnection<Postgres>>),
+    DryRun(MutexGuard<'a, Option<Transaction<'static, Postgres>>>),
+}
+
+impl Deref for Conn<'_> {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        match self {
+            Conn::Pooled(conn) => conn,
+            Conn::DryRun(tx) => tx.as_ref().expect("the dry run should not be over"),
+        }
+    }
+}
+
+impl DerefMut for Conn<'_> {
+    fn deref_mut(&mut self) -> &mut PgConnection {
+        match self {
+            Conn::Pooled(conn) => conn,
+            Conn::DryRun(tx) => tx.as_mut().expect("the dry run should not be over"),
+        }
+    }
+}
+
+/// Period of time to sync courses in
+#[derive(Debug, Clone, Copy)]
+pub enum Window {
+    Explicit {
+        start: NaiveDate,
+        end: NaiveDate,
+    },
+    /// From the last rollover day (month, day) to the next one
+    AcademicYear {
+        rollover: (u32, u32),
+    },
+    Rolling {
+        weeks_back: i64,
+        weeks_ahead: i64,
+    },
+}
+
+impl Window {
+    /// Every option can also be set by an environment variable
+    pub fn from_args(matches: &ArgMatches) -> anyhow::Result<Self> {
+        let arg = |name: &str, var: &str| arg(matches, name, var);
+        let date = |name: &str, var: &str| -> anyhow::Result<Option<NaiveDate>> {
+            arg(name, var)
+                .map(|d| {
+                    NaiveDate::parse_from_str(&d, "%Y-%m-%d")
+                        .with_context(|| format!("Invalid date for {}: {}", name, d))
+                })
+                .transpose()
+        };
+        let weeks = |name: &str, var: &str| -> anyhow::Result<Option<i64>> {
+            arg(name, var)
+                .map(|w| {
+                    w.parse()
+                        .with_context(|| format!("Invalid number of weeks for {}: {}", name, w))
+                })
+                .transpose()
+        };
+
+        let start = date("START", "SYNC_START")?;
+        let end = date("END", "SYNC_END")?;
+        let weeks_back = weeks("WEEKS_BACK", "SYNC_WEEKS_BACK")?;
+        let weeks_ahead = weeks("WEEKS_AHEAD", "SYNC_WEEKS_AHEAD")?;
+        // A non-leap year, so that the rollover day exists every year
+        let rollover = arg("ROLLOVER", "SYNC_ROLLOVER")
+            .map(|r| -> anyhow::Result<(u32, u32)> {
+                let d = NaiveDate::parse_from_str(&format!("2001-{}", r), "%Y-%m-%d")
+                    .with_context(|| format!("Invalid rollover day: {}", r))?;
+                Ok((d.month(), d.day()))
+            })
+            .transpose()?;
+
+        match (start, end, weeks_back, weeks_ahead, rollover) {
+            (Some(start), Some(end), None, None, None) => Ok(Window::Explicit { start, end }),
+            (None, None, Some(weeks_back), Some(weeks_ahead), None) => Ok(Window::Rolling {
+                weeks_back,
+                weeks_ahead,
+            }),
+            (None, None, None, None, rollover) => Ok(Window::AcademicYear {
+                rollover: rollover.unwrap_or((9, 1)),
+            }),
+            (_, _, _, _, Some(_)) => Err(anyhow!(
+                "The rollover day can't be combined with a start, an end or weeks back and ahead"
+            )),
+            _ => Err(anyhow!(
+                "Either give both a start and an end, both weeks back and ahead, or none of them"
+            )),
+        }
+    }
+
+    pub fn range(&self, today: NaiveDate) -> anyhow::Result<(NaiveDateTime, NaiveDateTime)> {
+        let (start, end) = match *self {
+            Window::Explicit { start, end } => (start, end),
+            Window::AcademicYear {
+                rollover: (month, day),
+            } => {
+                let rollover = |year| {
+                    NaiveDate::from_ymd_opt(year, month, day)
+                        .ok_or_else(|| anyhow!("Invalid rollover day: {}-{}", month, day))
+                };
+                let mut year = today.year();
+                if today < rollover(year)? {
+                    year -= 1;
+                }
+                (rollover(year)?, rollover(year + 1)?)
+            }
+            Window::Rolling {
+                weeks_back,
+                weeks_ahead,
+            } => (
+                today - ChronoDuration::weeks(weeks_back),
+                today + ChronoDuration::weeks(weeks_ahead),
+            ),
+        };
+
+        if start >= end {
+            return Err(anyhow!("The sync window is empty: {} to {}", start, end));
+        }
+
+        Ok((start.and_time(NaiveTime::MIN), end.and_time(NaiveTime::MIN)))
+    }
+}
+
+/// How the sync of a group went
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Every course was updated
+    Complete,
+    /// Some side bar events couldn't be fetched, the other courses were committed
+    Partial,
+    /// Nothing was committed
+    Failed,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Complete => "complete",
+            Outcome::Partial => "partial",
+            Outcome::Failed => "failed",
+        }
+    }
+}
+
+/// What changed during a run
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Summary {
+    pub courses_inserted: usize,
+    pub courses_moved: usize,
+    pub courses_updated: usize,
+    pub courses_unchanged: usize,
+    /// Courses whose side bar event wasn't fetched, their calendar entry being unchanged
+    pub courses_cached: usize,
+    /// Courses whose side bar event couldn't be fetched
+    pub courses_failed: usize,
+    pub links_inserted: usize,
+    pub links_cancelled: usize,
+    pub groups_complete: usize,
+    pub groups_partial: usize,
+    pub groups_failed: usize,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Courses:")?;
+        writeln!(f, "  inserted:  {}", self.courses_inserted)?;
+        writeln!(f, "  moved:     {}", self.courses_moved)?;
+        writeln!(f, "  updated:   {}", self.courses_updated)?;
+        writeln!(f, "  unchanged: {}", self.courses_unchanged)?;
+        writeln!(f, "  cached:    {}", self.courses_cached)?;
+        writeln!(f, "  failed:    {}", self.courses_failed)?;
+        writeln!(f, "Group courses:")?;
+        writeln!(f, "  inserted:  {}", self.links_inserted)?;
+        writeln!(f, "  cancelled: {}", self.links_cancelled)?;
+        writeln!(f, "Groups:")?;
+        writeln!(f, "  complete:  {}", self.groups_complete)?;
+        writeln!(f, "  partial:   {}", self.groups_partial)?;
+        write!(f, "  failed:    {}", self.groups_failed)
+    }
+}
+
+/// A course as stored
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CourseState {
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+    pub category: Option<String>,
+    pub module: Option<String>,
+    pub room: Option<String>,
+    pub teacher: Option<String>,
+    pub description: Option<String>,
+}
+
+impl fmt::Display for CourseState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_dash = |s: &Option<String>| s.clone().unwrap_or_else(|| "-".to_owned());
+        write!(
+            f,
+            "{} to {}, {}, {}, {}",
+            self.start,
+            self.end
+                .map(|end| end.to_string())
+                .unwrap_or_else(|| "-".to_owned()),
+            or_dash(&self.category),
+            or_dash(&self.room),
+            or_dash(&self.teacher)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NewCourse {
+    pub id: String,
+    #[serde(flatten)]
+    pub course: CourseState,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangedCourse {
+    pub id: String,
+    pub old: CourseState,
+    pub new: CourseState,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupCourse {
+    pub group: i32,
+    pub course: String,
+}
+
+/// A group whose sync wasn't complete
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupFailure {
+    pub group: i32,
+    pub outcome: Outcome,
+    pub error: String,
+}
+
+/// What changed during a run, course by course
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Report {
+    pub new_courses: Vec<NewCourse>,
+    pub changed_courses: Vec<ChangedCourse>,
+    pub new_links: Vec<GroupCourse>,
+    /// Courses cancelled for a group
+    pub removed_links: Vec<GroupCourse>,
+    pub failed_groups: Vec<GroupFailure>,
+}
+
+impl Report {
+    fn sort(&mut self) {
+        self.new_courses
+            .sort_by(|a, b| (a.course.start, &a.id).cmp(&(b.course.start, &b.id)));
+        self.changed_courses
+            .sort_by(|a, b| (a.new.start, &a.id).cmp(&(b.new.start, &b.id)));
+        let link = |l: &GroupCourse| (l.group, l.course.clone());
+        self.new_links.sort_by_key(link);
+        self.removed_links.sort_by_key(link);
+        self.failed_groups.sort_by_key(|g| g.group);
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "New courses:")?;
+        for c in &self.new_courses {
+            writeln!(f, "  {}: {}", c.id, c.course)?;
+        }
+        writeln!(f, "Changed courses:")?;
+        for c in &self.changed_courses {
+            writeln!(f, "  {}: {}", c.id, c.old)?;
+            writeln!(f, "  {}  -> {}", " ".repeat(c.id.len()), c.new)?;
+        }
+        writeln!(f, "New group courses:")?;
+        for l in &self.new_links {
+            writeln!(f, "  group {}: {}", l.group, l.course)?;
+        }
+        writeln!(f, "Removed group courses:")?;
+        for l in &self.removed_links {
+            writeln!(f, "  group {}: {}", l.group, l.course)?;
+        }
+        writeln!(f, "Failed groups:")?;
+        for g in &self.failed_groups {
+            writeln!(f, "  group {} ({:?}): {}", g.group, g.outcome, g.error)?;
+        }
+        Ok(())
+    }
+}
+
+/// [`Summary`] being counted by concurrent tasks
+#[derive(Default)]
+struct Counters {
+    courses_inserted: AtomicUsize,
+    courses_moved: AtomicUsize,
+    courses_updated: AtomicUsize,
+    courses_unchanged: AtomicUsize,
+    courses_cached: AtomicUsize,
+    courses_failed: AtomicUsize,
+    links_inserted: AtomicUsize,
+    links_cancelled: AtomicUsize,
+    groups_complete: AtomicUsize,
+    groups_partial: AtomicUsize,
+    groups_failed: AtomicUsize,
+}
+
+impl Counters {
+    fn summary(&self) -> Summary {
+        let get = |n: &AtomicUsize| n.load(Ordering::Relaxed);
+        Summary {
+            courses_inserted: get(&self.courses_inserted),
+            courses_moved: get(&self.courses_moved),
+            courses_updated: get(&self.courses_updated),
+            courses_unchanged: get(&self.courses_unchanged),
+            courses_cached: get(&self.courses_cached),
+            courses_failed: get(&self.courses_failed),
+            links_inserted: get(&self.links_inserted),
+            links_cancelled: get(&self.links_cancelled),
+            groups_complete: get(&self.groups_complete),
+            groups_partial: get(&self.groups_partial),
+            groups_failed: get(&self.groups_failed),
+        }
+    }
+}
+
+/// A course whose side bar event must be updated, and where to send whether it was
+type Message = (Course, oneshot::Sender<bool>);
+
+/// Update of a side bar event, shared by every group having the course
+type EventUpdate = Shared<BoxFuture<'static, bool>>;
+
+/// Retries of a side bar event update, after a first failure
+const RETRIES: usize = 4;
+
+/// Timezone of the campus, `Europe/Paris` by default
+pub fn timezone(matches: &ArgMatches) -> anyhow::Result<Tz> {
+    match arg(matches, "TIMEZONE", "SYNC_TIMEZONE") {
+        Some(tz) => tz
+            .parse()
+            .map_err(|err| anyhow!("Invalid timezone: {}", err)),
+        None => Ok(chrono_tz::Europe::Paris),
+    }
+}
+
+/// Age of the side bar events after which they're fetched again, a day by default
+pub fn event_max_age(matches: &ArgMatches) -> anyhow::Result<ChronoDuration> {
+    match arg(matches, "EVENT_MAX_AGE", "SYNC_EVENT_MAX_AGE") {
+        Some(age) => age
+            .parse()
+            .map(ChronoDuration::seconds)
+            .with_context(|| format!("Invalid number of seconds: {}", age)),
+        None => Ok(ChronoDuration::days(1)),
+    }
+}
+
+/// Sync the courses of every group having a referent, from `start` to `end`, rolling everything
+/// back if it's a dry run
+pub async fn sync(
+    pool: &PgPool,
+    celcat: Client,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    timezone: Tz,
+    event_max_age: ChronoDuration,
+    dry_run: bool,
+) -> anyhow::Result<(Summary, Report)> {
+    info!("syncing courses from {} to {}", start, end);
+
+    let dry_run = if dry_run {
+        Some(pool.begin().await?)
+    } else {
+        None
+    };
+
+    let state = Arc::new(State {
+        pool: pool.clone(),
+        celcat,
+        counters: Counters::default(),
+        start,
+        end,
+        timezone,
+        event_max_age,
+        dry_run: Mutex::new(dry_run),
+        report: Mutex::default(),
+    });
+
+    let gr = get_group_referents(&state.pool)
+        .await
+        .context("Failed to get groups referents")?;
+
+    let (tx, rx) = mpsc::channel(100);
+    let tx_ref = &tx;
+
+    let handle = tokio::spawn(event_updater(Arc::clone(&state), rx));
+
+    join_all(gr.into_iter().map(|(g, r)| {
+        let state = &state;
+        async move {
+            let outcome = match update_courses(state, g, r, tx_ref.clone()).await {
+                Ok(outcome) => outcome,
+                Err(err) => {
+                    error!("Failed to update courses of group {}: {:#}", g, err);
+                    state.report.lock().await.failed_groups.push(GroupFailure {
+                        group: g,
+                        outcome: Outcome::Failed,
+                        error: format!("{:#}", err),
+                    });
+                    Outcome::Failed
+                }
+            };
+            let counter = match outcome {
+                Outcome::Complete => &state.counters.groups_complete,
+                Outcome::Partial => &state.counters.groups_partial,
+                Outcome::Failed => &state.counters.groups_failed,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }))
+    .await;
+
+    drop(tx);
+    handle.await?;
+
+    if let Some(tx) = state.dry_run.lock().await.take() {
+        tx.rollback().await?;
+    }
+
+    let mut report = state.report.lock().await.clone();
+    report.sort();
+
+    Ok((state.counters.summary(), report))
+}
+
+async fn get_group_referents(pool: &PgPool) -> anyhow::Result<Vec<(i32, StudentId)>> {
+    let referents = sqlx::query!(
+        r#"
+SELECT id, referent
+FROM groups
+WHERE referent IS NOT NULL
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(referents
+        .into_iter()
+        .map(|r| {
+            (
+                r.id,
+                StudentId(
+                    r.referent
+                        .expect("the query should not return any null values")
+                        .to_string(),
+                ),
+            )
+        })
+        .collect())
+}
+
+async fn update_courses(
+    state: &State,
+    group: i32,
+    referent: StudentId,
+    s: mpsc::Sender<Message>,
+) -> anyhow::Result<Outcome> {
+    let (start, end) = (state.start, state.end);
+
+    let calendar: CalendarData<Student> = state
+        .celcat
+        .fetch(CalendarDataRequest {
+            start,
+            end,
+            res_type: Student,
+            cal_view: CalView::Month,
+            federation_ids: referent,
+            colour_scheme: 3,
+        })
+        .await?;
+
+    let updated = try_join_all(
+        calendar
+            .courses
+            .iter()
+            .map(|c| request_event_update(c, s.clone())),
+    )
+    .await?;
+    let failed = updated.iter().filter(|&&updated| !updated).count();
+
+    let outcome = if failed == 0 {
+        Outcome::Complete
+    } else if failed < updated.len() {
+        Outcome::Partial
+    } else {
+        Outcome::Failed
+    };
+    if outcome != Outcome::Complete {
+        let error = format!(
+            "{} of {} side bar events couldn't be updated",
+            failed,
+            updated.len()
+        );
+        warn!("Failed to update courses of group {}: {}", group, error);
+        state.report.lock().await.failed_groups.push(GroupFailure {
+            group,
+            outcome,
+            error,
+        });
+        if outcome == Outcome::Failed {
+            // Celcat is probably down, keep the group as it was
+            return Ok(outcome);
+        }
+    }
+
+    let mut conn = state.acquire().await?;
+    let mut tx = conn.begin().await?;
+
+    let linked = get_linked_courses(
+        &mut tx,
+        group,
+        localize(state.timezone, start)?,
+        localize(state.timezone, end)?,
+    )
+    .await?;
+    let fetched: HashSet<&str> = calendar.courses.iter().map(|c| c.id.0.as_str()).collect();
+
+    let mut cancelled = Vec::new();
+    for id in linked.iter().filter(|id| !fetched.contains(id.as_str())) {
+        cancel_course(&mut tx, group, id).await?;
+        cancelled.push(id.clone());
+    }
+
+    // Courses whose side bar event failed are only linked by a later run
+    let mut inserted = Vec::new();
+    for (c, _) in calendar
+        .courses
+        .iter()
+        .zip(updated)
+        .filter(|&(c, updated)| updated && !linked.contains(&c.id.0))
+    {
+        link_course(&mut tx, group, &c.id.0).await?;
+        inserted.push(c.id.0.clone());
+    }
+
+    tx.commit().await?;
+    drop(conn);
+
+    state
+        .counters
+        .links_inserted
+        .fetch_add(inserted.len(), Ordering::Relaxed);
+    state
+        .counters
+        .links_cancelled
+        .fetch_add(cancelled.len(), Ordering::Relaxed);
+
+    let mut report = state.report.lock().await;
+    let links = |courses: Vec<String>| {
+        courses
+            .into_iter()
+            .map(|course| GroupCourse { group, course })
+    };
+    report.new_links.extend(links(inserted));
+    report.removed_links.extend(links(cancelled));
+
+    Ok(outcome)
+}
+
+/// Courses of `group` starting between `start` and `end`
+async fn get_linked_courses(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    group: i32,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> anyhow::Result<HashSet<String>> {
+    Ok(sqlx::query!(
+        r#"
+SELECT c.id
+FROM courses AS c
+JOIN groups_courses AS gc ON gc.course_id = c.id
+WHERE gc.group_id = $1 AND c.start_time >= $2 AND c.start_time < $3
+        "#,
+        group,
+        start,
+        end
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|r| r.id)
+    .collect())
+}
+
+/// Remove a course which disappeared from the calendar of `group`
+async fn cancel_course(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    group: i32,
+    course: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+DELETE FROM groups_courses
+WHERE group_id = $1 AND course_id = $2
+        "#,
+        group,
+        course
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+INSERT INTO courses_changes (course_id, group_id, kind, old_start_time, old_end_time, old_room)
+SELECT id, $2, 'cancelled', start_time, end_time, room
+FROM courses
+WHERE id = $1
+        "#,
+        course,
+        group
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Returns whether the side bar event of `course` was updated
+async fn request_event_update(course: &Course, s: mpsc::Sender<Message>) -> anyhow::Result<bool> {
+    let (otx, orx) = oneshot::channel();
+    s.send((course.clone(), otx)).await?;
+    Ok(orx.await?)
+}
+
+/// Add a course to the calendar of `group`
+async fn link_course(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    group: i32,
+    course: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO groups_courses (group_id, course_id)
+VALUES ( $1, $2 )
+        "#,
+        group,
+        course
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+INSERT INTO courses_changes (course_id, group_id, kind, new_start_time, new_end_time, new_room)
+SELECT id, $2, 'inserted', start_time, end_time, room
+FROM courses
+WHERE id = $1
+        "#,
+        course,
+        group
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn event_updater(state: Arc<State>, mut rx: mpsc::Receiver<Message>) {
+    let mut already_updated = HashMap::<String, EventUpdate>::new();
+
+    while let Some((c, s)) = rx.recv().await {
+        let update = match already_updated.get(&c.id.0) {
+            // A failed update is evicted, so that this group tries again
+            Some(update) if update.peek() != Some(&false) => update.clone(),
+            _ => {
+                let update = update_event_with_retries(Arc::clone(&state), c.clone())
+                    .boxed()
+                    .shared();
+                already_updated.insert(c.id.0.clone(), update.clone());
+                update
+            }
+        };
+
+        tokio::spawn(async move {
+            if s.send(update.await).is_err() {
+                warn!("The receiver dropped");
+            }
+        });
+    }
+
+    // Every group got its answers, so the last update of each course is over
+    let mut failed = 0;
+    for update in already_updated.into_values() {
+        if !update.await {
+            failed += 1;
+        }
+    }
+    state
+        .counters
+        .courses_failed
+        .store(failed, Ordering::Relaxed);
+}
+
+/// Returns whether the side bar event was updated, in at most `RETRIES + 1` attempts
+async fn update_event_with_retries(state: Arc<State>, course: Course) -> bool {
+    // 0.5, 1, 2 and 4 seconds
+    let strategy = ExponentialBackoff::from_millis(2)
+        .factor(250)
+        .max_delay(Duration::from_secs(60))
+        .map(jitter)
+        .take(RETRIES);
+
+    match Retry::start(strategy, || update_event(&state, course.clone())).await {
+        Ok(()) => true,
+        Err(err) => {
+            error!(
+                "Failed to update side bar event of course {}: {:#}",
+                course.id.0, err
+            );
+            false
+        }
+    }
+}
+
+async fn update_event(state: &State, course: Course) -> anyhow::Result<()> {
+    let start = course.start.to_string();
+    let end = course.end.map(|end| end.to_string());
+    let calendar_fingerprint =
+        fingerprint(&[Some(&start), end.as_deref(), Some(&course.description)]);
+
+    let cached = sqlx::query!(
+        r#"
+SELECT calendar, fetched_at
+FROM courses_fingerprints
+WHERE course_id = $1
+        "#,
+        course.id.0
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+    if let Some(cached) = cached {
+        if cached.calendar == calendar_fingerprint
+            && Utc::now() - cached.fetched_at < state.event_max_age
+        {
+            state
+                .counters
+                .courses_cached
+                .fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+    }
+
+    let event: Event = state
+        .celcat
+        .fetch(EventRequest {
+            event_id: course.id.clone(),
+        })
+        .await
+        .context("Failed to fetch side bar event")?;
+
+    let mut category: Option<String> = None;
+    let mut module: Option<String> = None;
+    let mut rooms: Vec<String> = Vec::new();
+    let mut teachers: Vec<String> = Vec::new();
+    let mut description: Option<String> = None;
+
+    for e in event.elements.0 {
+        use Element::*;
+        match e {
+            Category(RawElement { content, .. }) => {
+                category = content;
+            }
+            Module(RawElement { content, .. }) => {
+                module = content;
+            }
+            Room(RawElement {
+                content: Some(content),
+                ..
+            }) => {
+                rooms.extend(split_list(&content, &[',']).map(str::to_owned));
+            }
+            Teacher(RawElement {
+                content: Some(content),
+                ..
+            }) => {
+                teachers.extend(split_list(&content, &[',', ';', '/']).map(str::to_owned));
+            }
+            Name(RawElement { content, .. }) => {
+                description = content;
+            }
+            _ => {}
+        }
+    }
+
+    let join = |list: &[String]| {
+        if list.is_empty() {
+            None
+        } else {
+            Some(list.join(", "))
+        }
+    };
+    let room = join(&rooms);
+    let teacher = join(&teachers);
+    let event_fingerprint = fingerprint(&[
+        category.as_deref(),
+        module.as_deref(),
+        room.as_deref(),
+        teacher.as_deref(),
+        description.as_deref(),
+    ]);
+
+    let new = CourseState {
+        start: localize(state.timezone, course.start)?,
+        end: course
+            .end
+            .map(|end| localize(state.timezone, end))
+            .transpose()?,
+        category,
+        module,
+        room,
+        teacher,
+        description,
+    };
+
+    let mut conn = state.acquire().await?;
+
+    let old = sqlx::query_as!(
+        CourseState,
+        r#"
+SELECT start_time AS start, end_time AS "end", category, module, room, teacher, description
+FROM courses
+WHERE id = $1
+        "#,
+        course.id.0
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    // Rooms and teachers are compared as sets, `room` and `teacher` used to
+    // only keep one of them
+    let mut relisted = false;
+    let old = match old {
+        Some(mut old) => {
+            if old.room != new.room
+                && linked_rooms(&mut *conn, &course.id.0).await? == sorted(&rooms)
+            {
+                old.room = new.room.clone();
+                relisted = true;
+            }
+            if old.teacher != new.teacher
+                && linked_teachers(&mut *conn, &course.id.0).await? == sorted(&teachers)
+            {
+                old.teacher = new.teacher.clone();
+                relisted = true;
+            }
+            Some(old)
+        }
+        None => None,
+    };
+
+    let moved = match &old {
+        Some(old) => {
+            if old == &new {
+                if relisted {
+                    sqlx::query!(
+                        r#"
+UPDATE courses
+SET room = $2, teacher = $3
+WHERE id = $1
+                        "#,
+                        course.id.0,
+                        new.room,
+                        new.teacher
+                    )
+                    .execute(&mut *conn)
+                    .await?;
+                }
+                update_fingerprints(
+                    &mut *conn,
+                    &course.id.0,
+                    &calendar_fingerprint,
+                    &event_fingerprint,
+                )
+                .await?;
+                state
+                    .counters
+                    .courses_unchanged
+                    .fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            old.start != new.start || old.end != new.end
+        }
+        None => false,
+    };
+
+    let mut tx = conn.begin().await?;
+
+    sqlx::query!(
+        r#"
+INSERT INTO courses
+    ( id
+    , start_time
+    , end_time
+    , category
+    , module
+    , room
+    , teacher
+    , description
+    )
+VALUES ( $1, $2, $3, $4, $5, $6, $7, $8 )
+ON CONFLICT (id) DO UPDATE
+SET ( start_time
+    , end_time
+    , category
+    , module
+    , room
+    , teacher
+    , description
+    ) = ( EXCLUDED.start_time
+        , EXCLUDED.end_time
+        , EXCLUDED.category
+        , EXCLUDED.module
+        , EXCLUDED.room
+        , EXCLUDED.teacher
+        , EXCLUDED.description
+        )
+        "#,
+        course.id.0,
+        new.start,
+        new.end,
+        new.category,
+        new.module,
+        new.room,
+        new.teacher,
+        new.description
+    )
+    .execute(&mut tx)
+    .await?;
+
+    update_rooms(&mut tx, &course.id.0, &rooms).await?;
+    update_teachers(&mut tx, &course.id.0, &teachers).await?;
+    update_fingerprints(
+        &mut tx,
+        &course.id.0,
+        &calendar_fingerprint,
+        &event_fingerprint,
+    )
+    .await?;
+
+    let counter = match &old {
+        Some(old) => {
+            sqlx::query!(
+                r#"
+INSERT INTO courses_changes
+    ( course_id
+    , kind
+    , old_start_time
+    , old_end_time
+    , old_room
+    , new_start_time
+    , new_end_time
+    , new_room
+    )
+VALUES ( $1, $2, $3, $4, $5, $6, $7, $8 )
+                "#,
+                course.id.0,
+                if moved { "moved" } else { "updated" },
+                old.start,
+                old.end,
+                old.room,
+                new.start,
+                new.end,
+                new.room
+            )
+            .execute(&mut tx)
+            .await?;
+
+            if moved {
+                &state.counters.courses_moved
+            } else {
+                &state.counters.courses_updated
+            }
+        }
+        None => &state.counters.courses_inserted,
+    };
+
+    tx.commit().await?;
+    drop(conn);
+
+    counter.fetch_add(1, Ordering::Relaxed);
+
+    let id = course.id.0;
+    let mut report = state.report.lock().await;
+    match old {
+        Some(old) => report.changed_courses.push(ChangedCourse { id, old, new }),
+        None => report.new_courses.push(NewCourse { id, course: new }),
+    }
+
+    Ok(())
+}
+
+/// Digest of `fields`, to tell whether they changed without keeping them
+fn fingerprint(fields: &[Option<&str>]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for field in fields {
+        match field {
+            Some(field) => {
+                hasher.update([1]);
+                hasher.update((field.len() as u64).to_le_bytes());
+                hasher.update(field);
+            }
+            None => hasher.update([0]),
+        }
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Remember the calendar entry and the side bar event of a course, just fetched
+async fn update_fingerprints<'c>(
+    executor: impl PgExecutor<'c>,
+    course: &str,
+    calendar: &[u8],
+    event: &[u8],
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO courses_fingerprints (course_id, calendar, event, fetched_at)
+VALUES ( $1, $2, $3, now() )
+ON CONFLICT (course_id) DO UPDATE
+SET (calendar, event, fetched_at) = (EXCLUDED.calendar, EXCLUDED.event, EXCLUDED.fetched_at)
+        "#,
+        course,
+        calendar,
+        event
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Split a list packed in a string, like `"SOME BODY, SOMEBODY ELSE"`
+fn split_list<'a>(list: &'a str, separators: &'a [char]) -> impl Iterator<Item = &'a str> {
+    list.split(separators)
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+}
+
+/// Link a course to its rooms, adding the new ones
+async fn update_rooms(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    course: &str,
+    rooms: &[String],
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO rooms (name)
+SELECT * FROM UNNEST($1::TEXT[])
+ON CONFLICT (name) DO NOTHING
+        "#,
+        rooms
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+DELETE FROM courses_rooms
+WHERE course_id = $1
+        "#,
+        course
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+INSERT INTO courses_rooms (course_id, room_id)
+SELECT $1, id
+FROM rooms
+WHERE name = ANY($2)
+        "#,
+        course,
+        rooms
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+/// A list without its order nor duplicates
+fn sorted(list: &[String]) -> Vec<String> {
+    let mut list = list.to_vec();
+    list.sort();
+    list.dedup();
+    list
+}
+
+/// Names of the rooms linked to a course, sorted
+async fn linked_rooms<'c>(
+    executor: impl PgExecutor<'c>,
+    course: &str,
+) -> sqlx::Result<Vec<String>> {
+    let mut rooms = sqlx::query_scalar!(
+        r#"
+SELECT r.name
+FROM courses_rooms AS cr
+JOIN rooms AS r ON r.id = cr.room_id
+WHERE cr.course_id = $1
+        "#,
+        course
+    )
+    .fetch_all(executor)
+    .await?;
+    rooms.sort();
+    Ok(rooms)
+}
+
+/// Names of the teachers linked to a course, sorted
+async fn linked_teachers<'c>(
+    executor: impl PgExecutor<'c>,
+    course: &str,
+) -> sqlx::Result<Vec<String>> {
+    let mut teachers = sqlx::query_scalar!(
+        r#"
+SELECT t.name
+FROM courses_teachers AS ct
+JOIN teachers AS t ON t.id = ct.teacher_id
+WHERE ct.course_id = $1
+        "#,
+        course
+    )
+    .fetch_all(executor)
+    .await?;
+    teachers.sort();
+    Ok(teachers)
+}
+
+/// Link a course to its teachers, adding the new ones
+async fn update_teachers(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    course: &str,
+    teachers: &[String],
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO teachers (name)
+SELECT * FROM UNNEST($1::TEXT[])
+ON CONFLICT (name) DO NOTHING
+        "#,
+        teachers
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+DELETE FROM courses_teachers
+WHERE course_id = $1
+        "#,
+        course
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+INSERT INTO courses_teachers (course_id, teacher_id)
+SELECT $1, id
+FROM teachers
+WHERE name = ANY($2)
+        "#,
+        course,
+        teachers
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,160 @@
+use serde_json::json;
+use sqlx::postgres::PgPool;
+
+use crate::courses::{self, GroupFailure};
+use crate::job::Job;
+use crate::students;
+
+/// How a run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Succeeded,
+    /// Some groups failed
+    Partial,
+    Failed,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Succeeded => "succeeded",
+            Status::Partial => "partial",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+/// A run being recorded in `sync_runs`, running until it's finished
+pub struct Run {
+    id: i32,
+}
+
+impl Run {
+    /// Record a run of `job`, whose lock must be held.
+    ///
+    /// The runs of the jobs sharing the lock that are still running were interrupted, they are
+    /// marked as failed.
+    pub async fn start(pool: &PgPool, job: Job) -> sqlx::Result<Self> {
+        let mut tx = pool.begin().await?;
+
+        let jobs: Vec<&str> = job.sharing_lock().into_iter().map(Job::as_str).collect();
+        sqlx::query!(
+            r#"
+UPDATE sync_runs
+SET status = 'failed', ended_at = now(), error = 'interrupted'
+WHERE status = 'running' AND job = ANY($1)
+            "#,
+            &jobs as &[&str]
+        )
+        .execute(&mut tx)
+        .await?;
+
+        let id = sqlx::query!(
+            r#"
+INSERT INTO sync_runs (job, status)
+VALUES ( $1, 'running' )
+RETURNING id
+            "#,
+            job.as_str()
+        )
+        .fetch_one(&mut tx)
+        .await?
+        .id;
+
+        tx.commit().await?;
+
+        Ok(Self { id })
+    }
+
+    pub async fn finish_courses(
+        self,
+        pool: &PgPool,
+        result: &anyhow::Result<(courses::Summary, courses::Report)>,
+    ) -> sqlx::Result<()> {
+        match result {
+            Ok((summary, report)) => {
+                let status = if summary.groups_failed + summary.groups_partial == 0 {
+                    Status::Succeeded
+                } else if summary.groups_complete + summary.groups_partial > 0 {
+                    Status::Partial
+                } else {
+                    Status::Failed
+                };
+                let counts = serde_json::to_value(summary).expect("the summary is serializable");
+                self.finish(pool, status, Some(counts), None, &report.failed_groups)
+                    .await
+            }
+            Err(err) => self.failed(pool, err).await,
+        }
+    }
+
+    pub async fn finish_students(
+        self,
+        pool: &PgPool,
+        result: &anyhow::Result<students::Report>,
+    ) -> sqlx::Result<()> {
+        match result {
+            Ok(report) => {
+                let counts = json!({
+                    "new": report.new.len(),
+                    "changed": report.changed.len(),
+                    "removed": report.removed.len(),
+                    "unchanged": report.unchanged,
+                });
+                self.finish(pool, Status::Succeeded, Some(counts), None, &[])
+                    .await
+            }
+            Err(err) => self.failed(pool, err).await,
+        }
+    }
+
+    async fn failed(self, pool: &PgPool, err: &anyhow::Error) -> sqlx::Result<()> {
+        self.finish(pool, Status::Failed, None, Some(format!("{:#}", err)), &[])
+            .await
+    }
+
+    async fn finish(
+        self,
+        pool: &PgPool,
+        status: Status,
+        counts: Option<serde_json::Value>,
+        error: Option<String>,
+        groups: &[GroupFailure],
+    ) -> sqlx::Result<()> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+UPDATE sync_runs
+SET (status, ended_at, counts, error) = ($2, now(), $3, $4)
+WHERE id = $1
+            "#,
+            self.id,
+            status.as_str(),
+            counts,
+            error
+        )
+        .execute(&mut tx)
+        .await?;
+
+        for g in groups {
+            // The group may have been deleted since
+            sqlx::query!(
+                r#"
+INSERT INTO sync_runs_groups (run_id, group_id, outcome, error)
+SELECT $1, id, $3, $4
+FROM groups
+WHERE id = $2
+                "#,
+                self.id,
+                g.group,
+                g.outcome.as_str(),
+                g.error
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+}
@@ -0,0 +1,71 @@
+use sqlx::postgres::{PgConnection, PgPool};
+use sqlx::Connection;
+
+/// What a sync run does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Job {
+    /// Courses of the whole sync window
+    Courses,
+    /// Courses of the next weeks
+    NearCourses,
+    Students,
+}
+
+impl Job {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Job::Courses => "courses",
+            Job::NearCourses => "near_courses",
+            Job::Students => "students",
+        }
+    }
+
+    /// Key of the advisory lock held during a run, jobs writing the same tables share it
+    fn lock_key(self) -> i64 {
+        match self {
+            Job::Courses | Job::NearCourses => 0x6379_7265_6c01,
+            Job::Students => 0x6379_7265_6c02,
+        }
+    }
+
+    /// Jobs sharing the lock of this one, itself included
+    pub fn sharing_lock(self) -> Vec<Job> {
+        [Job::Courses, Job::NearCourses, Job::Students]
+            .into_iter()
+            .filter(|j| j.lock_key() == self.lock_key())
+            .collect()
+    }
+}
+
+/// A PostgreSQL advisory lock, held by its own connection so that it is released if the
+/// connection is lost
+pub struct Lock {
+    conn: PgConnection,
+    key: i64,
+}
+
+impl Lock {
+    pub async fn release(mut self) -> sqlx::Result<()> {
+        sqlx::query!("SELECT pg_advisory_unlock($1)", self.key)
+            .fetch_one(&mut self.conn)
+            .await?;
+        self.conn.close().await
+    }
+}
+
+/// Lock `job`, unless another run already holds the lock
+pub async fn try_lock(pool: &PgPool, job: Job) -> sqlx::Result<Option<Lock>> {
+    let mut conn = pool.acquire().await?.detach();
+    let locked = sqlx::query!(
+        r#"SELECT pg_try_advisory_lock($1) AS "locked!""#,
+        job.lock_key()
+    )
+    .fetch_one(&mut conn)
+    .await?
+    .locked;
+
+    Ok(locked.then(|| Lock {
+        conn,
+        key: job.lock_key(),
+    }))
+}
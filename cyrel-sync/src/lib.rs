@@ -0,0 +1,17 @@
+use std::env;
+
+use clap::ArgMatches;
+
+pub mod client;
+pub mod courses;
+pub mod history;
+pub mod job;
+pub mod students;
+
+/// Value of the option `name`, or of the environment variable `var`
+pub fn arg(matches: &ArgMatches, name: &str, var: &str) -> Option<String> {
+    matches
+        .value_of(name)
+        .map(str::to_owned)
+        .or_else(|| env::var(var).ok())
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use anyhow::anyhow;
+use celcat::{
+    entities::Student,
+    fetchable::resources::{ResourceList, ResourceListRequest},
+};
+use serde::Serialize;
+use sqlx::postgres::PgPool;
+use tracing::info;
+
+use crate::client::Client;
+
+/// A student of `celcat_students`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CelcatStudent {
+    pub id: i64,
+    pub firstname: String,
+    pub lastname: String,
+    pub department: String,
+}
+
+impl fmt::Display for CelcatStudent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} ({})",
+            self.id, self.firstname, self.lastname, self.department
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangedStudent {
+    pub old: CelcatStudent,
+    pub new: CelcatStudent,
+}
+
+/// What a sync changed
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Report {
+    pub new: Vec<CelcatStudent>,
+    pub changed: Vec<ChangedStudent>,
+    /// Students who aren't in Celcat anymore, they are kept
+    pub removed: Vec<CelcatStudent>,
+    pub unchanged: usize,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "New students:")?;
+        for s in &self.new {
+            writeln!(f, "  {}", s)?;
+        }
+        writeln!(f, "Changed students:")?;
+        for c in &self.changed {
+            writeln!(f, "  {} -> {}", c.old, c.new)?;
+        }
+        writeln!(f, "Removed students (kept):")?;
+        for s in &self.removed {
+            writeln!(f, "  {}", s)?;
+        }
+        write!(f, "Unchanged students: {}", self.unchanged)
+    }
+}
+
+/// Sync the students of every department, rolling back if it's a dry run
+pub async fn sync(pool: &PgPool, celcat: &Client, dry_run: bool) -> anyhow::Result<Report> {
+    let students: ResourceList<Student> = celcat
+        .fetch(ResourceListRequest {
+            my_resources: false,
+            search_term: "__".to_owned(),
+            page_size: 1000000,
+            page_number: 0,
+            res_type: Student,
+        })
+        .await?;
+
+    let mut tx = pool.begin().await?;
+
+    let mut old: HashMap<i64, CelcatStudent> = sqlx::query_as!(
+        CelcatStudent,
+        r#"
+SELECT id, firstname, lastname, department
+FROM celcat_students
+        "#
+    )
+    .fetch_all(&mut tx)
+    .await?
+    .into_iter()
+    .map(|s| (s.id, s))
+    .collect();
+
+    let mut report = Report::default();
+    for s in &students.results {
+        let (firstname, lastname) = separate_names(&s.text)?;
+        let new = CelcatStudent {
+            id: s.id.0.parse()?,
+            firstname,
+            lastname,
+            department: s.dept.clone(),
+        };
+        match old.remove(&new.id) {
+            Some(old) if old == new => {
+                report.unchanged += 1;
+                continue;
+            }
+            Some(old) => report.changed.push(ChangedStudent {
+                old,
+                new: new.clone(),
+            }),
+            None => report.new.push(new.clone()),
+        }
+
+        sqlx::query!(
+            r#"
+INSERT INTO celcat_students (id, firstname, lastname, department)
+VALUES ( $1, $2, $3, $4 )
+ON CONFLICT (id) DO UPDATE
+SET (firstname, lastname, department) = (EXCLUDED.firstname, EXCLUDED.lastname, EXCLUDED.department)
+            "#,
+            new.id,
+            new.firstname,
+            new.lastname,
+            new.department
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    report.removed = old.into_values().collect();
+    report.removed.sort_by_key(|s| s.id);
+
+    if dry_run {
+        tx.rollback().await?;
+    } else {
+        tx.commit().await?;
+    }
+
+    info!("synced {} students", students.results.len());
+
+    Ok(report)
+}
+
+fn separate_names(name: &str) -> anyhow::Result<(String, String)> {
+    let name: String = name
+        .split_inclusive(|c: char| !c.is_alphabetic())
+        .map(|w| {
+            let mut cs = w.chars();
+            match cs.next() {
+                Some(c) => c.to_string() + &cs.as_str().to_lowercase(),
+                None => String::new(),
+            }
+        })
+        .collect();
+
+    match name.rsplit_once(' ') {
+        Some((l, f)) => Ok((f.to_owned(), l.to_owned())),
+        _ => Err(anyhow!(
+            "Can't split '{}' into firstname and lastname",
+            name
+        )),
+    }
+}
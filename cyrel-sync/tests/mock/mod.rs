@@ -0,0 +1,112 @@
+//! A fake Celcat answering with the fixtures of `tests/resources`:
+//!
+//! - `calendar_data/<federation id>.json` for `GetCalendarData`,
+//! - `side_bar_event/<event id>.json` for `GetSideBarEvent`, `:` being replaced by `_`,
+//! - `resource_list/<resource type>.json` for `ReadResourceListItems`.
+//!
+//! Requests without a fixture get a 404.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+
+const LOGIN_PAGE: &str = r#"<form action="/calendar/LdapLogin/Logon" method="post">
+<input name="__RequestVerificationToken" type="hidden" value="mock-token" />
+</form>"#;
+
+pub fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../tests/resources")
+}
+
+/// Requests being answered, the most of them at the same time, and the side bar events fetched
+#[derive(Default)]
+pub struct Load {
+    current: AtomicUsize,
+    pub peak: AtomicUsize,
+    pub events: AtomicUsize,
+}
+
+/// Start the server in the background, returning its base URL
+pub fn start() -> String {
+    start_slow(Duration::ZERO).0
+}
+
+/// Start a server answering the Celcat requests after `delay`
+pub fn start_slow(delay: Duration) -> (String, Arc<Load>) {
+    let fixtures = Arc::new(fixtures());
+    let load = Arc::new(Load::default());
+    let make_service = {
+        let load = Arc::clone(&load);
+        make_service_fn(move |_| {
+            let fixtures = Arc::clone(&fixtures);
+            let load = Arc::clone(&load);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let fixtures = Arc::clone(&fixtures);
+                    let load = Arc::clone(&load);
+                    async move {
+                        let current = load.current.fetch_add(1, Ordering::SeqCst) + 1;
+                        load.peak.fetch_max(current, Ordering::SeqCst);
+                        if req.uri().path().ends_with("/GetSideBarEvent") {
+                            load.events.fetch_add(1, Ordering::SeqCst);
+                        }
+                        tokio::time::sleep(delay).await;
+                        let res = handle(fixtures, req).await;
+                        load.current.fetch_sub(1, Ordering::SeqCst);
+                        res
+                    }
+                }))
+            }
+        })
+    };
+
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+    let url = format!("http://{}/calendar", server.local_addr());
+    tokio::spawn(server);
+    (url, load)
+}
+
+async fn handle(fixtures: Arc<PathBuf>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let path = req.uri().path().to_owned();
+    let body = hyper::body::to_bytes(req.into_body())
+        .await
+        .unwrap_or_default();
+    let form: HashMap<String, String> = url::form_urlencoded::parse(&body).into_owned().collect();
+
+    let fixture = match path.strip_prefix("/calendar") {
+        Some("/LdapLogin") => return Ok(Response::new(LOGIN_PAGE.into())),
+        Some("/LdapLogin/Logon") => return Ok(Response::new(Body::empty())),
+        Some("/Home/GetCalendarData") => form
+            .get("federationIds")
+            .map(|id| format!("calendar_data/{}.json", id)),
+        Some("/Home/GetSideBarEvent") => form
+            .get("eventId")
+            .map(|id| format!("side_bar_event/{}.json", id.replace(':', "_"))),
+        Some("/Home/ReadResourceListItems") => form
+            .get("resType")
+            .map(|t| format!("resource_list/{}.json", t)),
+        _ => None,
+    };
+
+    Ok(
+        match fixture.and_then(|f| fs::read(fixtures.join(f)).ok()) {
+            Some(json) => Response::builder()
+                .header(CONTENT_TYPE, "application/json; charset=utf-8")
+                .body(json.into())
+                .expect("the response should be valid"),
+            None => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .expect("the response should be valid"),
+        },
+    )
+}
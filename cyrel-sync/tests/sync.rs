@@ -0,0 +1,637 @@
+//! Run the sync binaries against the mock Celcat and a throwaway database.
+//!
+//! `DATABASE_URL` must point to a PostgreSQL server where databases can be created, the tests are
+//! skipped if it isn't set.
+
+mod mock;
+
+use std::env;
+use std::process::ExitStatus;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use cyrel_sync::job::{self, Job};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tokio::process::Command;
+use url::Url;
+
+/// Create an empty database for a test, returning its URL
+async fn database(name: &str) -> Option<String> {
+    let _ = dotenv::dotenv();
+    let url = match env::var("DATABASE_URL") {
+        Ok(url) => url,
+        Err(_) => {
+            eprintln!("DATABASE_URL isn't set, skipping");
+            return None;
+        }
+    };
+
+    let name = format!("cyrel_sync_test_{}", name);
+    let server = PgPool::connect(&url).await.unwrap();
+    sqlx::query(&format!("DROP DATABASE IF EXISTS {}", name))
+        .execute(&server)
+        .await
+        .unwrap();
+    sqlx::query(&format!("CREATE DATABASE {}", name))
+        .execute(&server)
+        .await
+        .unwrap();
+
+    let mut url = Url::parse(&url).unwrap();
+    url.set_path(&name);
+    Some(url.into())
+}
+
+/// Run a sync binary, migrating the database first
+async fn run(bin: &str, database: &str, celcat: &str, args: &[&str]) -> ExitStatus {
+    command(bin, database, celcat, args).status().await.unwrap()
+}
+
+fn command(bin: &str, database: &str, celcat: &str, args: &[&str]) -> Command {
+    let mut command = Command::new(bin);
+    command
+        .arg("--migrate")
+        .args(args)
+        .env("DATABASE_URL", database)
+        .env("CELCAT_URL", celcat)
+        .env("CELCAT_USERNAME", "username")
+        .env("CELCAT_PASSWORD", "password")
+        .env_remove("SYNC_START")
+        .env_remove("SYNC_END")
+        .env_remove("SYNC_ROLLOVER")
+        .env_remove("SYNC_WEEKS_BACK")
+        .env_remove("SYNC_WEEKS_AHEAD")
+        .env_remove("SYNC_TIMEZONE")
+        .env_remove("CELCAT_CONCURRENCY")
+        .env_remove("CELCAT_RATE")
+        .env_remove("SYNC_EVENT_MAX_AGE");
+    command
+}
+
+async fn sync(bin: &str, database: &str, celcat: &str, args: &[&str]) {
+    let status = run(bin, database, celcat, args).await;
+    assert!(status.success(), "{} failed: {}", bin, status);
+}
+
+fn utc(month: u32, day: u32, hour: u32, min: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2022, month, day, hour, min, 0)
+        .unwrap()
+}
+
+/// Create an empty database for the test `name`, migrate it and connect to it.
+///
+/// Returns the URLs of the database and of the mock Celcat, and the pool.
+async fn setup(name: &str, celcat: String) -> Option<(String, String, PgPool)> {
+    let database = database(name).await?;
+    sync(
+        env!("CARGO_BIN_EXE_cyrel-sync-courses"),
+        &database,
+        &celcat,
+        &["--check-schema"],
+    )
+    .await;
+    let pool = PgPool::connect(&database).await.unwrap();
+    Some((database, celcat, pool))
+}
+
+/// Add the group `name`, whose referent is the student `referent`
+async fn seed_group(pool: &PgPool, referent: i64, name: &str) -> i32 {
+    sqlx::query(
+        "INSERT INTO users (id, firstname, lastname, email, password)
+         VALUES ($1, 'Test', $2, $1 || '@cyrel.invalid', '')",
+    )
+    .bind(referent)
+    .bind(name)
+    .execute(pool)
+    .await
+    .unwrap();
+    let (group,): (i32,) = sqlx::query_as(
+        "INSERT INTO groups (name, referent, private) VALUES ($1, $2, false) RETURNING id",
+    )
+    .bind(name)
+    .bind(referent)
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    group
+}
+
+/// Add courses synced before to `group`, with only a start
+async fn seed_courses(pool: &PgPool, group: i32, courses: &[(&str, DateTime<Utc>)]) {
+    for (id, start) in courses {
+        sqlx::query("INSERT INTO courses (id, start_time) VALUES ($1, $2)")
+            .bind(id)
+            .bind(start)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO groups_courses (group_id, course_id) VALUES ($1, $2)")
+            .bind(group)
+            .bind(id)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+}
+
+/// The group of the student 21900001, with a course gone from Celcat and one out of the synced
+/// week
+async fn seed_l1(pool: &PgPool) -> i32 {
+    let group = seed_group(pool, 21900001, "L1").await;
+    seed_courses(
+        pool,
+        group,
+        &[("gone", utc(5, 4, 8, 0)), ("last-week", utc(4, 27, 8, 0))],
+    )
+    .await;
+    group
+}
+
+#[tokio::test]
+async fn sync_courses() {
+    let (database, celcat, pool) = match setup("courses", mock::start()).await {
+        Some(setup) => setup,
+        None => return,
+    };
+    let bin = env!("CARGO_BIN_EXE_cyrel-sync-courses");
+    let args = [
+        "--start",
+        "2022-05-02",
+        "--end",
+        "2022-05-09",
+        "--timezone",
+        "Europe/Paris",
+    ];
+
+    let group = seed_l1(&pool).await;
+
+    sync(bin, &database, &celcat, &args).await;
+
+    #[allow(clippy::type_complexity)]
+    let courses: Vec<(
+        String,
+        DateTime<Utc>,
+        Option<DateTime<Utc>>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+    )> = sqlx::query_as(
+        "SELECT c.id, c.start_time, c.end_time, c.category, c.room, c.teacher
+         FROM courses AS c
+         JOIN groups_courses AS gc ON gc.course_id = c.id
+         WHERE gc.group_id = $1
+         ORDER BY c.start_time",
+    )
+    .bind(group)
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    let some = |s: &str| Some(s.to_owned());
+    assert_eq!(
+        courses,
+        [
+            (
+                "last-week".to_owned(),
+                utc(4, 27, 8, 0),
+                None,
+                None,
+                None,
+                None
+            ),
+            (
+                "-1131429482:-1131429482:1:41001:1".to_owned(),
+                utc(5, 2, 6, 30),
+                Some(utc(5, 2, 8, 0)),
+                some("CM"),
+                some("A ROOM, B ROOM"),
+                some("SOME BODY"),
+            ),
+            (
+                "-1131429482:-1131429482:1:41002:1".to_owned(),
+                utc(5, 3, 11, 30),
+                Some(utc(5, 3, 14, 45)),
+                some("TD"),
+                some("C ROOM"),
+                some("SOME BODY, SOMEBODY ELSE"),
+            ),
+            (
+                "-1131429482:-1131429482:1:41003:1".to_owned(),
+                utc(5, 5, 8, 0),
+                None,
+                some("Examen"),
+                some("A ROOM"),
+                None,
+            ),
+        ]
+    );
+
+    let rooms: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT r.name, count(*) FROM rooms AS r
+         JOIN courses_rooms AS cr ON cr.room_id = r.id
+         GROUP BY r.name ORDER BY r.name",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        rooms,
+        [
+            ("A ROOM".to_owned(), 2),
+            ("B ROOM".to_owned(), 1),
+            ("C ROOM".to_owned(), 1),
+        ]
+    );
+
+    let teachers: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT t.name, count(*) FROM teachers AS t
+         JOIN courses_teachers AS ct ON ct.teacher_id = t.id
+         GROUP BY t.name ORDER BY t.name",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        teachers,
+        [("SOME BODY".to_owned(), 2), ("SOMEBODY ELSE".to_owned(), 1)]
+    );
+
+    let changes = || async {
+        let changes: Vec<(String, String)> =
+            sqlx::query_as("SELECT course_id, kind FROM courses_changes ORDER BY course_id, id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        changes
+    };
+    let expected = [
+        ("-1131429482:-1131429482:1:41001:1", "inserted"),
+        ("-1131429482:-1131429482:1:41002:1", "inserted"),
+        ("-1131429482:-1131429482:1:41003:1", "inserted"),
+        ("gone", "cancelled"),
+    ]
+    .map(|(id, kind)| (id.to_owned(), kind.to_owned()));
+    assert_eq!(changes().await, expected);
+
+    // Nothing changed in Celcat
+    sync(bin, &database, &celcat, &args).await;
+    assert_eq!(changes().await, expected);
+
+    // Only the last room and teacher were kept by older syncs
+    let legacy = "-1131429482:-1131429482:1:41002:1";
+    sqlx::query("UPDATE courses SET room = 'B ROOM' WHERE id = $1")
+        .bind("-1131429482:-1131429482:1:41001:1")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE courses SET teacher = 'SOMEBODY ELSE' WHERE id = $1")
+        .bind(legacy)
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("DELETE FROM courses_fingerprints")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sync(bin, &database, &celcat, &args).await;
+    assert_eq!(changes().await, expected);
+    let listed: Vec<(Option<String>, Option<String>)> =
+        sqlx::query_as("SELECT room, teacher FROM courses WHERE id LIKE '-%' ORDER BY start_time")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        listed,
+        courses[1..]
+            .iter()
+            .map(|c| (c.4.clone(), c.5.clone()))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn sync_students() {
+    let (database, celcat, pool) = match setup("students", mock::start()).await {
+        Some(setup) => setup,
+        None => return,
+    };
+
+    sync(
+        env!("CARGO_BIN_EXE_cyrel-sync-students"),
+        &database,
+        &celcat,
+        &[],
+    )
+    .await;
+
+    let students: Vec<(i64, String, String, String)> = sqlx::query_as(
+        "SELECT id, firstname, lastname, department FROM celcat_students ORDER BY id",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        students,
+        [
+            (21900001, "Jean", "Dupont", "CY TECH"),
+            (21900002, "Claire", "Martin", "CY TECH"),
+        ]
+        .map(|(id, firstname, lastname, department)| (
+            id,
+            firstname.to_owned(),
+            lastname.to_owned(),
+            department.to_owned()
+        ))
+    );
+}
+
+#[tokio::test]
+async fn locked_sync() {
+    let (database, celcat, pool) = match setup("locked", mock::start()).await {
+        Some(setup) => setup,
+        None => return,
+    };
+    let bin = env!("CARGO_BIN_EXE_cyrel-sync-courses");
+
+    let lock = job::try_lock(&pool, Job::NearCourses)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(job::try_lock(&pool, Job::Courses).await.unwrap().is_none());
+    assert!(!run(bin, &database, &celcat, &[]).await.success());
+
+    lock.release().await.unwrap();
+    assert!(job::try_lock(&pool, Job::Courses).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn partial_sync() {
+    let (database, celcat, pool) = match setup("partial", mock::start()).await {
+        Some(setup) => setup,
+        None => return,
+    };
+    let bin = env!("CARGO_BIN_EXE_cyrel-sync-courses");
+
+    let group = seed_group(&pool, 21900002, "L2").await;
+    // Synced before, its side bar event is now missing
+    let missing = "-1131429482:-1131429482:1:41004:1";
+    seed_courses(&pool, group, &[(missing, utc(5, 6, 6, 30))]).await;
+
+    sync(
+        bin,
+        &database,
+        &celcat,
+        &["--start", "2022-05-02", "--end", "2022-05-09"],
+    )
+    .await;
+
+    // The course that could be fetched is committed, the other one is kept as it was
+    let courses: Vec<(String, Option<String>)> = sqlx::query_as(
+        "SELECT c.id, c.category FROM courses AS c
+         JOIN groups_courses AS gc ON gc.course_id = c.id
+         WHERE gc.group_id = $1
+         ORDER BY c.start_time",
+    )
+    .bind(group)
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        courses,
+        [
+            (
+                "-1131429482:-1131429482:1:41001:1".to_owned(),
+                Some("CM".to_owned())
+            ),
+            (missing.to_owned(), None),
+        ]
+    );
+    let (changes,): (i64,) =
+        sqlx::query_as("SELECT count(*) FROM courses_changes WHERE course_id = $1")
+            .bind(missing)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(changes, 0);
+
+    let (status, partial, ended): (String, Value, bool) = sqlx::query_as(
+        "SELECT status, counts -> 'groups_partial', ended_at >= started_at
+         FROM sync_runs WHERE job = 'courses'",
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        (status.as_str(), partial, ended),
+        ("partial", json!(1), true)
+    );
+    let groups: Vec<(i32, String, String)> =
+        sqlx::query_as("SELECT group_id, outcome, error FROM sync_runs_groups")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        groups,
+        [(
+            group,
+            "partial".to_owned(),
+            "1 of 2 side bar events couldn't be updated".to_owned()
+        )]
+    );
+}
+
+#[tokio::test]
+async fn limited_sync() {
+    let (celcat, load) = mock::start_slow(Duration::from_millis(200));
+    let (database, celcat, pool) = match setup("limited", celcat).await {
+        Some(setup) => setup,
+        None => return,
+    };
+    let bin = env!("CARGO_BIN_EXE_cyrel-sync-courses");
+
+    seed_l1(&pool).await;
+    load.peak.store(0, Ordering::SeqCst);
+
+    // The three side bar events are fetched two at a time
+    sync(
+        bin,
+        &database,
+        &celcat,
+        &[
+            "--start",
+            "2022-05-02",
+            "--end",
+            "2022-05-09",
+            "--celcat-concurrency",
+            "2",
+            "--celcat-rate",
+            "1000",
+        ],
+    )
+    .await;
+    assert_eq!(load.peak.load(Ordering::SeqCst), 2);
+
+    // Requests can't be sent at a null or tiny rate
+    for rate in ["0", "1e-320"] {
+        let args = ["--celcat-rate", rate];
+        assert!(!run(bin, &database, &celcat, &args).await.success());
+    }
+}
+
+#[tokio::test]
+async fn cached_sync() {
+    let (celcat, load) = mock::start_slow(Duration::ZERO);
+    let (database, celcat, pool) = match setup("cached", celcat).await {
+        Some(setup) => setup,
+        None => return,
+    };
+    let bin = env!("CARGO_BIN_EXE_cyrel-sync-courses");
+    let args = ["--start", "2022-05-02", "--end", "2022-05-09"];
+
+    seed_l1(&pool).await;
+
+    sync(bin, &database, &celcat, &args).await;
+    assert_eq!(load.events.load(Ordering::SeqCst), 3);
+
+    // The calendar entries didn't change
+    sync(bin, &database, &celcat, &args).await;
+    assert_eq!(load.events.load(Ordering::SeqCst), 3);
+
+    sync(
+        bin,
+        &database,
+        &celcat,
+        &[&args[..], &["--event-max-age", "0"]].concat(),
+    )
+    .await;
+    assert_eq!(load.events.load(Ordering::SeqCst), 6);
+
+    // The entry of a course changed
+    sqlx::query("UPDATE courses_fingerprints SET calendar = '' WHERE course_id = $1")
+        .bind("-1131429482:-1131429482:1:41002:1")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sync(bin, &database, &celcat, &args).await;
+    assert_eq!(load.events.load(Ordering::SeqCst), 7);
+}
+
+#[tokio::test]
+async fn dry_run() {
+    let (database, celcat, pool) = match setup("dry_run", mock::start()).await {
+        Some(setup) => setup,
+        None => return,
+    };
+    let courses = env!("CARGO_BIN_EXE_cyrel-sync-courses");
+    let students = env!("CARGO_BIN_EXE_cyrel-sync-students");
+
+    let group = seed_l1(&pool).await;
+    sqlx::query(
+        "INSERT INTO celcat_students (id, firstname, lastname, department)
+         VALUES (21900001, 'Jean', 'Dupont', 'CY'), (21900003, 'Paul', 'Durand', 'CY TECH')",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let dry_run = |bin, args: &'static [&'static str]| {
+        let (database, celcat) = (database.clone(), celcat.clone());
+        async move {
+            let output = command(bin, &database, &celcat, args)
+                .args(["--dry-run", "--json"])
+                .output()
+                .await
+                .unwrap();
+            assert!(output.status.success(), "{} failed", bin);
+            serde_json::from_slice::<Value>(&output.stdout).unwrap()
+        }
+    };
+
+    let report =
+        &dry_run(courses, &["--start", "2022-05-02", "--end", "2022-05-09"]).await["report"];
+    let ids = |list: &Value, field: &str| -> Vec<Value> {
+        list.as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v[field].clone())
+            .collect()
+    };
+    let fetched = [
+        "-1131429482:-1131429482:1:41001:1",
+        "-1131429482:-1131429482:1:41002:1",
+        "-1131429482:-1131429482:1:41003:1",
+    ]
+    .map(Value::from);
+    assert_eq!(ids(&report["new_courses"], "id"), fetched);
+    assert_eq!(report["new_courses"][0]["room"], "A ROOM, B ROOM");
+    assert_eq!(ids(&report["changed_courses"], "id"), [] as [Value; 0]);
+    assert_eq!(ids(&report["new_links"], "course"), fetched);
+    assert_eq!(
+        report["removed_links"],
+        json!([{ "group": group, "course": "gone" }])
+    );
+
+    let report = dry_run(students, &[]).await;
+    assert_eq!(ids(&report["new"], "id"), [Value::from(21900002)]);
+    assert_eq!(report["changed"][0]["new"]["department"], "CY TECH");
+    assert_eq!(ids(&report["removed"], "id"), [Value::from(21900003)]);
+
+    // Nothing was written
+    let count = |table: &'static str| {
+        let pool = pool.clone();
+        async move {
+            let (count,): (i64,) = sqlx::query_as(&format!("SELECT count(*) FROM {}", table))
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            count
+        }
+    };
+    assert_eq!(count("courses").await, 2);
+    assert_eq!(count("groups_courses").await, 2);
+    assert_eq!(count("courses_changes").await, 0);
+    assert_eq!(count("celcat_students").await, 2);
+    assert_eq!(count("sync_runs").await, 0);
+}
+
+#[tokio::test]
+async fn interrupted_sync() {
+    let (database, celcat, pool) = match setup("interrupted", mock::start()).await {
+        Some(setup) => setup,
+        None => return,
+    };
+
+    // Killed while syncing
+    sqlx::query("INSERT INTO sync_runs (job, status) VALUES ('near_courses', 'running'), ('students', 'running')")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    sync(
+        env!("CARGO_BIN_EXE_cyrel-sync-courses"),
+        &database,
+        &celcat,
+        &["--start", "2022-05-02", "--end", "2022-05-09"],
+    )
+    .await;
+
+    let runs: Vec<(String, String, Option<String>)> =
+        sqlx::query_as("SELECT job, status, error FROM sync_runs ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        runs,
+        [
+            ("near_courses", "failed", Some("interrupted")),
+            ("students", "running", None),
+            ("courses", "succeeded", None),
+        ]
+        .map(|(job, status, error)| (
+            job.to_owned(),
+            status.to_owned(),
+            error.map(str::to_owned)
+        ))
+    );
+}
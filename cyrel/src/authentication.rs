@@ -1,9 +1,13 @@
+use std::net::IpAddr;
+
+use argon2::{
+    password_hash::{self, PasswordHash, PasswordHasher, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
 use jsonrpc_core::Metadata;
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
-use pbkdf2::{
-    password_hash::{PasswordHasher, Salt},
-    Pbkdf2,
-};
+use pbkdf2::Pbkdf2;
+use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use tracing::warn;
@@ -14,6 +18,8 @@ use crate::SETTINGS;
 #[derive(Debug, Default, Clone)]
 pub struct Meta {
     pub jwt: Option<String>,
+    /// Address of the client, as forwarded by the trusted reverse proxy, unknown without one
+    pub ip: Option<IpAddr>,
 }
 
 impl Metadata for Meta {}
@@ -22,6 +28,8 @@ impl Metadata for Meta {}
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
+    /// Session ID
+    pub sid: String,
 }
 
 impl Claims {
@@ -43,11 +51,12 @@ impl Claims {
         .map(|j| Some(j.claims))
     }
 
-    pub fn from_user(user: &User) -> Self {
-        let time = chrono::offset::Utc::now() + chrono::Duration::days(14);
+    pub fn new(user: i64, session: &str) -> Self {
+        let time = chrono::offset::Utc::now() + chrono::Duration::seconds(SETTINGS.jwt.access);
         Claims {
-            sub: user.id.to_string(),
+            sub: user.to_string(),
             exp: time.timestamp() as usize,
+            sid: session.to_owned(),
         }
     }
 
@@ -61,25 +70,95 @@ impl Claims {
 }
 
 pub async fn logged_user_get(pool: &PgPool, meta: Meta) -> anyhow::Result<Option<User>> {
-    let claims = match Claims::from_meta(&meta, &SETTINGS.jwt.secret)? {
-        Some(claims) => claims,
-        None => {
+    Ok(logged_session_get(pool, meta).await?.map(|(user, _)| user))
+}
+
+/// Get the logged user and the ID of their session, if the session is still live
+pub async fn logged_session_get(
+    pool: &PgPool,
+    meta: Meta,
+) -> anyhow::Result<Option<(User, String)>> {
+    let claims = match Claims::from_meta(&meta, &SETTINGS.jwt.secret) {
+        Ok(Some(claims)) => claims,
+        Ok(None) => {
             warn!("User not logged!");
             return Ok(None);
         }
+        Err(err) => {
+            warn!("Invalid JWT: {}", err);
+            return Ok(None);
+        }
     };
 
-    Ok(sqlx::query_as!(
+    let user = sqlx::query_as!(
         User,
-        "select * from users where id = $1",
+        "select u.* from users as u
+         join sessions as s on s.user_id = u.id
+         where u.id = $1 and s.id = $2 and s.expires_at > now() and not u.disabled",
         claims.sub.parse::<i64>()?,
+        claims.sid,
     )
     .fetch_optional(pool)
-    .await?)
+    .await?;
+
+    if user.is_none() {
+        warn!("Session {} is closed", claims.sid);
+    }
+
+    Ok(user.map(|u| (u, claims.sid)))
 }
 
-pub fn hash_password(password: &str, salt: &str) -> pbkdf2::password_hash::Result<String> {
-    Ok(Pbkdf2
-        .hash_password_simple(password.as_bytes(), &Salt::new(salt)?)?
+/// Argon2 parameters from the settings
+fn params() -> Params {
+    Params {
+        m_cost: SETTINGS.hashing.memory,
+        t_cost: SETTINGS.hashing.iterations,
+        p_cost: SETTINGS.hashing.parallelism,
+        output_size: Params::DEFAULT_OUTPUT_SIZE,
+        version: Version::V0x13,
+    }
+}
+
+/// Hash a password with Argon2id and a random salt, into a PHC string
+pub fn hash_password(password: &str) -> password_hash::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(
+            password.as_bytes(),
+            Some(Algorithm::Argon2id.ident()),
+            params(),
+            salt.as_salt(),
+        )?
         .to_string())
 }
+
+pub enum Verification {
+    Invalid,
+    Valid,
+    /// Valid, but hashed with PBKDF2 or other parameters: it should be hashed again
+    Outdated,
+}
+
+/// Verify a password against a PHC string, hashed with Argon2 or PBKDF2
+pub fn verify_password(password: &str, hash: &str) -> password_hash::Result<Verification> {
+    let hash = PasswordHash::new(hash)?;
+    if hash
+        .verify_password(&[&Argon2::default(), &Pbkdf2], password)
+        .is_err()
+    {
+        return Ok(Verification::Invalid);
+    }
+
+    let params = params();
+    let current = hash.algorithm == Algorithm::Argon2id.ident()
+        && matches!(
+            Params::try_from(&hash),
+            Ok(p) if (p.m_cost, p.t_cost, p.p_cost) == (params.m_cost, params.t_cost, params.p_cost)
+        );
+
+    Ok(if current {
+        Verification::Valid
+    } else {
+        Verification::Outdated
+    })
+}
@@ -0,0 +1,188 @@
+use chrono::{DateTime, Duration, Utc};
+use jsonrpc_http_server::hyper::{self, header, Body, Method, Request, Response, StatusCode};
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+
+use crate::schedule::{self, Course, Page};
+use crate::tokens;
+use crate::SETTINGS;
+
+/// How many weeks of past courses are included in a feed
+const PAST_WEEKS: i64 = 4;
+/// How many weeks of future courses are included in a feed
+const FUTURE_WEEKS: i64 = 52;
+
+/// Extract the token and the group from a feed request: `GET /calendar/<token>/<group>.ics`
+pub fn route(req: &Request<Body>) -> Option<(String, i32)> {
+    if req.method() != Method::GET {
+        return None;
+    }
+    let (token, group) = req
+        .uri()
+        .path()
+        .strip_prefix("/calendar/")?
+        .split_once('/')?;
+    let group = group.strip_suffix(".ics")?.parse().ok()?;
+    Some((token.to_owned(), group))
+}
+
+pub async fn serve(pool: PgPool, token: String, group: i32) -> hyper::Result<Response<Body>> {
+    let (status, body) = match feed(&pool, &token, group).await {
+        Ok(Some(cal)) => (StatusCode::OK, cal),
+        Ok(None) => (StatusCode::NOT_FOUND, "".to_owned()),
+        Err(err) => {
+            error!("{}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "".to_owned())
+        }
+    };
+
+    Ok(Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/calendar; charset=utf-8")
+        .body(body.into())
+        .expect("the response should be valid"))
+}
+
+async fn feed(pool: &PgPool, token: &str, group: i32) -> anyhow::Result<Option<String>> {
+    let user = match sqlx::query!(
+        "select t.user_id from calendar_tokens as t
+         join users as u on u.id = t.user_id
+         where t.token = $1 and not u.disabled",
+        token,
+    )
+    .fetch_optional(pool)
+    .await?
+    {
+        Some(t) => t.user_id,
+        None => {
+            warn!(
+                "Someone tried to use an inexistant or disabled calendar token: {}",
+                tokens::redact(token)
+            );
+            return Ok(None);
+        }
+    };
+
+    if !schedule::can_see(pool, user, group).await? {
+        warn!("{} can't see the calendar of group {}", user, group);
+        return Ok(None);
+    }
+
+    let now = Utc::now();
+    let courses = schedule::get(
+        pool,
+        &SETTINGS.schedule.durations,
+        group,
+        now - Duration::weeks(PAST_WEEKS),
+        now + Duration::weeks(FUTURE_WEEKS),
+        Page::default(),
+    )
+    .await?;
+
+    info!("{} fetched the calendar of group {}", user, group);
+    Ok(Some(render(&courses)))
+}
+
+/// Get the calendar token of `user`, creating it if needed
+pub async fn token_get(pool: &PgPool, user: i64) -> sqlx::Result<String> {
+    Ok(sqlx::query!(
+        "insert into calendar_tokens (token, user_id)
+         values ($1, $2)
+         on conflict (user_id) do update set token = calendar_tokens.token
+         returning token",
+        uuid::Uuid::new_v4().to_string(),
+        user,
+    )
+    .fetch_one(pool)
+    .await?
+    .token)
+}
+
+/// Revoke the calendar token of `user`, a new one will be created by the next [`token_get`]
+pub async fn token_revoke(pool: &PgPool, user: i64) -> sqlx::Result<()> {
+    sqlx::query!("delete from calendar_tokens where user_id = $1", user)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Render courses as an iCalendar (RFC 5545).
+///
+/// Course times are written in UTC.
+pub fn render(courses: &[Course]) -> String {
+    let stamp = format_time(Utc::now());
+
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_owned(),
+        "VERSION:2.0".to_owned(),
+        "PRODID:-//Cyrel//Cyrel//FR".to_owned(),
+        "CALSCALE:GREGORIAN".to_owned(),
+        "X-WR-CALNAME:Cyrel".to_owned(),
+    ];
+
+    for course in courses {
+        lines.push("BEGIN:VEVENT".to_owned());
+        lines.push(format!("UID:{}@cyrel", escape(&course.id)));
+        lines.push(format!("DTSTAMP:{}", stamp));
+        lines.push(format!("DTSTART:{}", format_time(course.start_time)));
+        lines.push(format!("DTEND:{}", format_time(course.end_time)));
+        if let Some(module) = &course.module {
+            lines.push(format!("SUMMARY:{}", escape(module)));
+        }
+        if let Some(room) = &course.room {
+            lines.push(format!("LOCATION:{}", escape(room)));
+        }
+        let description: Vec<&str> = [&course.teacher, &course.description]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        if !description.is_empty() {
+            lines.push(format!("DESCRIPTION:{}", escape(&description.join("\n"))));
+        }
+        if let Some(category) = &course.category {
+            lines.push(format!("CATEGORIES:{}", escape(category)));
+        }
+        lines.push("END:VEVENT".to_owned());
+    }
+
+    lines.push("END:VCALENDAR".to_owned());
+
+    lines.iter().map(|l| fold(l) + "\r\n").collect()
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Escape a TEXT value
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Fold a content line so that no line is longer than 75 octets
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len());
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            len = 1;
+        }
+        folded.push(c);
+        len += c.len_utf8();
+    }
+    folded
+}
@@ -1,11 +1,23 @@
 use askama::Template;
 use lettre::{
     message::{MultiPart, SinglePart},
-    Message,
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, Message, Tokio1Executor,
 };
 
 use crate::SETTINGS;
 
+pub fn mailer() -> Result<AsyncSmtpTransport<Tokio1Executor>, lettre::transport::smtp::Error> {
+    Ok(
+        AsyncSmtpTransport::<Tokio1Executor>::relay(&SETTINGS.smtp.server)?
+            .credentials(Credentials::new(
+                SETTINGS.smtp.username.clone(),
+                SETTINGS.smtp.password.clone(),
+            ))
+            .build(),
+    )
+}
+
 fn gen<T, H>(email: &str, subject: &str, txt: T, html: H) -> anyhow::Result<Message>
 where
     T: Template,
@@ -82,3 +94,53 @@ pub fn gen_reset(
         },
     )
 }
+
+/// A change of a course, ready to be displayed
+pub struct Change {
+    /// `moved`, `cancelled` or `updated`
+    pub kind: String,
+    pub module: String,
+    /// Empty if unknown
+    pub old_time: String,
+    pub new_time: String,
+    pub old_room: String,
+    pub new_room: String,
+}
+
+#[derive(Template)]
+#[template(path = "notifications.txt")]
+struct NotificationsTxtTemplate<'a> {
+    firstname: &'a str,
+    lastname: &'a str,
+    changes: &'a [Change],
+}
+
+#[derive(Template)]
+#[template(path = "notifications.html")]
+struct NotificationsHtmlTemplate<'a> {
+    firstname: &'a str,
+    lastname: &'a str,
+    changes: &'a [Change],
+}
+
+pub fn gen_notifications(
+    email: &str,
+    firstname: &str,
+    lastname: &str,
+    changes: &[Change],
+) -> anyhow::Result<Message> {
+    gen(
+        email,
+        "Changements dans votre emploi du temps",
+        NotificationsTxtTemplate {
+            firstname,
+            lastname,
+            changes,
+        },
+        NotificationsHtmlTemplate {
+            firstname,
+            lastname,
+            changes,
+        },
+    )
+}
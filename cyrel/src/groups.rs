@@ -1 +1,52 @@
+use sqlx::PgPool;
 
+/// Whether making `parent` the parent of `group` would create a cycle
+pub async fn would_cycle(pool: &PgPool, group: i32, parent: i32) -> sqlx::Result<bool> {
+    Ok(sqlx::query!(
+        "with recursive ancestors (id, parent) as (
+             select id, parent from groups where id = $1
+             union
+             select g.id, g.parent from groups as g
+             join ancestors as a on g.id = a.parent
+         )
+         select from ancestors where id = $2",
+        parent,
+        group,
+    )
+    .fetch_optional(pool)
+    .await?
+    .is_some())
+}
+
+/// Delete a group and its memberships, its children are moved to its parent.
+///
+/// Returns whether the group existed.
+pub async fn delete(pool: &PgPool, group: i32) -> sqlx::Result<bool> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!("delete from users_groups where group_id = $1", group)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query!("delete from groups_courses where group_id = $1", group)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query!("delete from courses_changes where group_id = $1", group)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query!(
+        "update groups set parent = (select parent from groups where id = $1)
+         where parent = $1",
+        group,
+    )
+    .execute(&mut tx)
+    .await?;
+    let deleted = sqlx::query!("delete from groups where id = $1", group)
+        .execute(&mut tx)
+        .await?
+        .rows_affected()
+        > 0;
+
+    tx.commit().await?;
+
+    Ok(deleted)
+}
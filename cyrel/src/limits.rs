@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::authentication::Meta;
+use crate::rpc::RpcError;
+use crate::SETTINGS;
+
+struct Hits {
+    since: Instant,
+    count: u32,
+}
+
+struct Failures {
+    last: Instant,
+    count: u32,
+}
+
+/// In-memory rate limiting of the sensitive methods
+#[derive(Default)]
+pub struct Limiter {
+    hits: Mutex<HashMap<String, Hits>>,
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+/// Emails are case insensitive, and often typed with spaces around
+fn email_key(email: &str) -> String {
+    format!("email:{}", email.trim().to_lowercase())
+}
+
+fn retry_after(until: Instant) -> RpcError {
+    RpcError::RateLimited {
+        retry_after: until
+            .saturating_duration_since(Instant::now())
+            .as_secs()
+            .max(1),
+    }
+}
+
+impl Limiter {
+    /// Count a request made by `key`, failing if it made more than `limit` requests during the
+    /// current window
+    fn hit(&self, key: &str, limit: u32) -> Result<(), RpcError> {
+        let window = Duration::from_secs(SETTINGS.limits.window);
+        let mut hits = self.hits.lock().unwrap();
+        hits.retain(|_, h| h.since.elapsed() < window);
+
+        let h = hits.entry(key.to_owned()).or_insert(Hits {
+            since: Instant::now(),
+            count: 0,
+        });
+        if h.count >= limit {
+            return Err(retry_after(h.since + window));
+        }
+        h.count += 1;
+
+        Ok(())
+    }
+
+    /// Count a sensitive request made by the client of `meta`, if its address is known
+    pub fn hit_ip(&self, meta: &Meta) -> Result<(), RpcError> {
+        match meta.ip {
+            Some(ip) => self.hit(&format!("ip:{}", ip), SETTINGS.limits.ip),
+            None => Ok(()),
+        }
+    }
+
+    /// Count an email sent to `email`
+    pub fn hit_email(&self, email: &str) -> Result<(), RpcError> {
+        self.hit(&email_key(email), SETTINGS.limits.email)
+    }
+
+    /// Lockout after `count` failures.
+    ///
+    /// It starts at `SETTINGS.limits.lockout.base` seconds, and doubles with each new failure.
+    fn lockout(count: u32) -> Option<Duration> {
+        let lockout = &SETTINGS.limits.lockout;
+        if count < lockout.after {
+            return None;
+        }
+        let factor = 2u64.saturating_pow(count - lockout.after);
+        Some(Duration::from_secs(
+            lockout.base.saturating_mul(factor).min(lockout.max),
+        ))
+    }
+
+    /// Fail if `email` is locked out
+    pub fn check(&self, email: &str) -> Result<(), RpcError> {
+        let failures = self.failures.lock().unwrap();
+        if let Some(f) = failures.get(&email_key(email)) {
+            if let Some(lockout) = Self::lockout(f.count) {
+                if f.last.elapsed() < lockout {
+                    return Err(retry_after(f.last + lockout));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record a failed login to `email`
+    pub fn fail(&self, email: &str) {
+        let max = Duration::from_secs(SETTINGS.limits.lockout.max);
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, f| f.last.elapsed() < max);
+
+        let f = failures.entry(email_key(email)).or_insert(Failures {
+            last: Instant::now(),
+            count: 0,
+        });
+        f.last = Instant::now();
+        f.count += 1;
+    }
+
+    /// Forget the failed logins to `email`
+    pub fn succeed(&self, email: &str) {
+        self.failures.lock().unwrap().remove(&email_key(email));
+    }
+}
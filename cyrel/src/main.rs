@@ -1,15 +1,30 @@
 mod authentication;
+mod calendar;
 mod email;
 mod groups;
+mod limits;
 mod models;
+mod notifications;
+mod password;
+mod permissions;
+mod rooms;
 mod rpc;
 mod schedule;
+mod sessions;
 mod settings;
+mod sync;
+mod teachers;
+mod timestamp;
+mod tokens;
+
+#[cfg(test)]
+mod tests;
 
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 use anyhow::anyhow;
 use clap::{clap_app, crate_authors, crate_description, crate_name, crate_version, ArgMatches};
+use cyrel_common::schema;
 use dotenv::dotenv;
 use jsonrpc_core::*;
 use jsonrpc_http_server::*;
@@ -31,6 +46,8 @@ lazy_static! {
             (about: crate_description!())
             (@arg CONFIG: -c --config +takes_value "config file to read")
             (@arg PORT: -p --port +takes_value "port to use")
+            (@arg MIGRATE: --migrate "apply the pending database migrations before starting")
+            (@arg CHECK_SCHEMA: --("check-schema") "check the database schema and exit")
     )
     .get_matches();
     static ref SETTINGS: Settings = Settings::new(&CLI).expect("failed to read settings");
@@ -53,7 +70,19 @@ async fn main() -> anyhow::Result<()> {
     let mut io = MetaIoHandler::default();
 
     let db = PgPoolOptions::new().connect(&SETTINGS.database.url).await?;
-    let rpc = RpcImpl::new(db).unwrap();
+    schema::ensure(&db, CLI.is_present("MIGRATE")).await?;
+    if CLI.is_present("CHECK_SCHEMA") {
+        info!("the database schema is up to date");
+        return Ok(());
+    }
+
+    tokio::spawn(tokens::purge_periodically(db.clone()));
+    tokio::spawn(notifications::notify_periodically(
+        db.clone(),
+        email::mailer()?,
+    ));
+
+    let rpc = RpcImpl::new(db.clone()).unwrap();
 
     io.extend_with(rpc.to_delegate());
 
@@ -69,11 +98,28 @@ async fn main() -> anyhow::Result<()> {
                 .and_then(|s| s.strip_prefix("Bearer ")) // FIXME: reliable?
                 .map(|s| s.to_owned());
             trace!("got JWT: {:?}", jwt);
-            Meta { jwt }
+            // The server only listens on localhost, so the last address
+            // was added by the reverse proxy, if there is one. Without it,
+            // jsonrpc-http-server doesn't give the address of the client.
+            let ip = req
+                .headers()
+                .get("X-Forwarded-For")
+                .and_then(|h| h.to_str().ok())
+                .and_then(|s| s.rsplit(',').next())
+                .and_then(|s| s.trim().parse().ok())
+                .filter(|_| SETTINGS.limits.proxy);
+            trace!("got IP: {:?}", ip);
+            Meta { jwt, ip }
         })
-        .request_middleware(|req: hyper::Request<hyper::Body>| {
+        .request_middleware(move |req: hyper::Request<hyper::Body>| {
             trace!("{:?}", req);
-            req.into()
+            match calendar::route(&req) {
+                Some((token, group)) => RequestMiddlewareAction::Respond {
+                    should_validate_hosts: true,
+                    response: Box::pin(calendar::serve(db.clone(), token, group)),
+                },
+                None => req.into(),
+            }
         })
         .start_http(&addr)
         .unwrap();
@@ -7,12 +7,21 @@ pub struct User {
     pub lastname: String,
     pub email: String,
     pub password: String,
+    /// Grants permissions to administrate Cyrel
+    pub role: Option<String>,
+    /// Disabled users can't log in
+    pub disabled: bool,
 }
 
-pub struct Department {
-    pub id: String,
-    pub name: String,
-    pub domain: String,
+/// A user, as seen by administrators
+#[derive(Serialize)]
+pub struct Account {
+    pub id: i64,
+    pub firstname: String,
+    pub lastname: String,
+    pub email: String,
+    pub role: Option<String>,
+    pub disabled: bool,
 }
 
 #[derive(Serialize)]
@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+
+use crate::email::{self, Change};
+use crate::SETTINGS;
+
+#[derive(Serialize)]
+pub struct Preferences {
+    /// Whether the user wants to receive digests
+    pub enabled: bool,
+    /// Only changes of courses in the next `days` days are sent
+    pub days: i32,
+}
+
+pub async fn preferences_get(pool: &PgPool, user: i64) -> sqlx::Result<Preferences> {
+    Ok(sqlx::query_as!(
+        Preferences,
+        "select enabled, days from notifications_preferences where user_id = $1",
+        user,
+    )
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or(Preferences {
+        enabled: false,
+        days: 7,
+    }))
+}
+
+pub async fn preferences_set(pool: &PgPool, user: i64, prefs: &Preferences) -> sqlx::Result<()> {
+    sqlx::query!(
+        "insert into notifications_preferences (user_id, enabled, days, notified_up_to)
+         values ($1, $2, $3, coalesce((select max(id) from courses_changes), 0))
+         on conflict (user_id) do update
+         set enabled = excluded.enabled,
+             days = excluded.days,
+             notified_up_to = case when notifications_preferences.enabled
+                                   then notifications_preferences.notified_up_to
+                                   else excluded.notified_up_to end",
+        user,
+        prefs.enabled,
+        prefs.days,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Format a time in the local time of the campus
+fn format_time(time: Option<DateTime<Utc>>) -> String {
+    time.map(|t| {
+        t.with_timezone(&SETTINGS.schedule.timezone)
+            .format("%d/%m/%Y %H:%M")
+            .to_string()
+    })
+    .unwrap_or_default()
+}
+
+/// Send a digest to every user having opted in, for the course changes logged by the sync since
+/// their last digest.
+///
+/// Only cancellations, moves and room changes of courses in the next days are sent. A user whose
+/// digest couldn't be sent gets it on the next call.
+pub async fn notify(
+    pool: &PgPool,
+    mailer: &AsyncSmtpTransport<Tokio1Executor>,
+) -> anyhow::Result<()> {
+    let last = match sqlx::query_scalar!("select max(id) from courses_changes")
+        .fetch_one(pool)
+        .await?
+    {
+        Some(last) => last,
+        None => return Ok(()),
+    };
+
+    let users = sqlx::query_scalar!(
+        "select user_id from notifications_preferences
+         where enabled and notified_up_to < $1
+         order by user_id",
+        last,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut sent = 0;
+    for user in users {
+        match notify_user(pool, mailer, user, last).await {
+            Ok(true) => sent += 1,
+            Ok(false) => {}
+            Err(err) => warn!("failed to notify {}: {}", user, err),
+        }
+    }
+
+    info!("sent {} schedule change digests", sent);
+
+    Ok(())
+}
+
+/// Send its digest to `user`, for the changes up to `last`, and return whether there was one.
+///
+/// The preferences of the user stay locked until the digest is sent, so that other instances skip
+/// the user instead of sending the same digest. They are only advanced once it was sent.
+async fn notify_user(
+    pool: &PgPool,
+    mailer: &AsyncSmtpTransport<Tokio1Executor>,
+    user: i64,
+    last: i32,
+) -> anyhow::Result<bool> {
+    let mut tx = pool.begin().await?;
+
+    let from = match sqlx::query_scalar!(
+        "select notified_up_to from notifications_preferences
+         where user_id = $1 and enabled and notified_up_to < $2
+         for update skip locked",
+        user,
+        last,
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    {
+        Some(from) => from,
+        None => return Ok(false),
+    };
+
+    let changes: Vec<Change> = sqlx::query!(
+        r#"select distinct c.id, c.kind, co.module,
+                  c.old_start_time, c.old_room, c.new_start_time, c.new_room
+           from courses_changes as c
+           join courses as co on co.id = c.course_id
+           join users_groups as ug on ug.group_id = c.group_id
+             or (c.group_id is null and ug.group_id in
+                  (select gc.group_id from groups_courses as gc where gc.course_id = c.course_id))
+           join notifications_preferences as np on np.user_id = ug.user_id
+           where ug.user_id = $1 and c.id > $2 and c.id <= $3
+             and (c.kind in ('cancelled', 'moved')
+                  or (c.kind = 'updated' and c.old_room is distinct from c.new_room))
+             and (c.old_start_time between now() and now() + make_interval(days => np.days)
+                  or c.new_start_time between now() and now() + make_interval(days => np.days))
+           order by c.id"#,
+        user,
+        from,
+        last,
+    )
+    .fetch_all(&mut tx)
+    .await?
+    .into_iter()
+    .map(|c| Change {
+        kind: c.kind,
+        module: c.module.unwrap_or_else(|| "Cours".to_owned()),
+        old_time: format_time(c.old_start_time),
+        new_time: format_time(c.new_start_time),
+        old_room: c.old_room.unwrap_or_default(),
+        new_room: c.new_room.unwrap_or_default(),
+    })
+    .collect();
+
+    let sent = !changes.is_empty();
+    if sent {
+        let u = sqlx::query!(
+            "select email, firstname, lastname from users where id = $1",
+            user,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        let message = email::gen_notifications(&u.email, &u.firstname, &u.lastname, &changes)?;
+        mailer.send(message).await?;
+    }
+
+    sqlx::query!(
+        "update notifications_preferences set notified_up_to = $2 where user_id = $1",
+        user,
+        last,
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(sent)
+}
+
+/// Send digests forever, every `SETTINGS.notifications.interval` seconds.
+pub async fn notify_periodically(pool: PgPool, mailer: AsyncSmtpTransport<Tokio1Executor>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(SETTINGS.notifications.interval));
+    loop {
+        interval.tick().await;
+        if let Err(err) = notify(&pool, &mailer).await {
+            error!("failed to send notifications: {}", err);
+        }
+    }
+}
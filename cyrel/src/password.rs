@@ -0,0 +1,69 @@
+use std::collections::HashSet;
+
+use lazy_static::lazy_static;
+
+use crate::models::User;
+use crate::rpc::RpcError;
+use crate::SETTINGS;
+
+lazy_static! {
+    static ref COMMON: HashSet<&'static str> = include_str!("../resources/common-passwords.txt")
+        .lines()
+        .collect();
+}
+
+/// Rules of the password policy broken by `password`, as stable identifiers for the clients
+fn broken_rules(password: &str, user: &User) -> Vec<&'static str> {
+    let policy = &SETTINGS.password;
+    let lowercase = password.to_lowercase();
+    let mut broken = Vec::new();
+
+    if password.chars().count() < policy.length {
+        broken.push("too_short");
+    }
+    if policy.lowercase && !password.chars().any(char::is_lowercase) {
+        broken.push("no_lowercase");
+    }
+    if policy.uppercase && !password.chars().any(char::is_uppercase) {
+        broken.push("no_uppercase");
+    }
+    if policy.digit && !password.chars().any(|c| c.is_ascii_digit()) {
+        broken.push("no_digit");
+    }
+    if policy.symbol && password.chars().all(char::is_alphanumeric) {
+        broken.push("no_symbol");
+    }
+
+    let names = user.firstname.split(|c: char| !c.is_alphabetic());
+    let names = names.chain(user.lastname.split(|c: char| !c.is_alphabetic()));
+    if names
+        .filter(|n| n.chars().count() >= 3)
+        .any(|n| lowercase.contains(&n.to_lowercase()))
+    {
+        broken.push("contains_name");
+    }
+    let id = user.id.to_string();
+    if id.len() >= 3 && lowercase.contains(&id) {
+        broken.push("contains_id");
+    }
+    if COMMON.contains(lowercase.as_str()) {
+        broken.push("common");
+    }
+
+    broken
+}
+
+/// Make sure `password` follows the password policy, for `user`.
+///
+/// Every broken rule is listed in the error.
+pub fn check(password: &str, user: &User) -> Result<(), RpcError> {
+    let broken = broken_rules(password, user);
+    if broken.is_empty() {
+        Ok(())
+    } else {
+        Err(RpcError::InvalidField {
+            field: "password".to_owned(),
+            reasons: broken.into_iter().map(str::to_owned).collect(),
+        })
+    }
+}
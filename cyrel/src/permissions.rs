@@ -0,0 +1,40 @@
+use sqlx::PgPool;
+
+use crate::models::User;
+
+/// What a role allows its users to manage
+#[derive(Clone, Copy, Debug)]
+pub enum Permission {
+    Groups,
+    Departments,
+    Clients,
+    Users,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Groups => "groups",
+            Permission::Departments => "departments",
+            Permission::Clients => "clients",
+            Permission::Users => "users",
+        }
+    }
+}
+
+/// Whether the role of `user` grants `permission`
+pub async fn has(pool: &PgPool, user: &User, permission: Permission) -> sqlx::Result<bool> {
+    let role = match &user.role {
+        Some(role) => role,
+        None => return Ok(false),
+    };
+
+    Ok(sqlx::query!(
+        "select from roles_permissions where role = $1 and permission = $2",
+        role,
+        permission.as_str(),
+    )
+    .fetch_optional(pool)
+    .await?
+    .is_some())
+}
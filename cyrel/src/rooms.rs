@@ -0,0 +1,106 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::schedule::Course;
+use crate::settings::Durations;
+
+#[derive(Debug, Serialize)]
+pub struct Room {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FreeRoom {
+    pub id: i32,
+    pub name: String,
+    /// Start of the next course in this room, if any
+    pub busy_at: Option<DateTime<Utc>>,
+}
+
+/// Rooms whose name contains `query`, ignoring case
+pub async fn search(pool: &PgPool, query: &str) -> sqlx::Result<Vec<Room>> {
+    sqlx::query_as!(
+        Room,
+        "select id, name from rooms
+         where strpos(lower(name), lower($1)) > 0
+         order by name
+         limit 50",
+        query,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn exists(pool: &PgPool, room: i32) -> sqlx::Result<bool> {
+    Ok(sqlx::query!("select id from rooms where id = $1", room)
+        .fetch_optional(pool)
+        .await?
+        .is_some())
+}
+
+/// Courses taking place in `room` overlapping `start` to `end`, among those of the groups
+/// `user` is allowed to see
+pub async fn schedule(
+    pool: &PgPool,
+    durations: &Durations,
+    user: i64,
+    room: i32,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> sqlx::Result<Vec<Course>> {
+    sqlx::query_as!(
+        Course,
+        r#"select c.id, c.start_time, course_end(c, $5, $6) as "end_time!", c.category,
+                  c.module, c.room, c.teacher, c.description
+           from courses as c
+           join courses_rooms as cr on c.id = cr.course_id
+           where cr.room_id = $2 and c.start_time < $4
+             and course_end(c, $5, $6) > $3
+             and can_see_course($1, c.id)
+           order by c.start_time, c.id"#,
+        user,
+        room,
+        start,
+        end,
+        json!(durations.categories),
+        durations.default,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Rooms with no course overlapping `start` to `end`.
+///
+/// Only the rooms starting with `building` are returned, if given.
+pub async fn free(
+    pool: &PgPool,
+    durations: &Durations,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    building: Option<&str>,
+) -> sqlx::Result<Vec<FreeRoom>> {
+    sqlx::query_as!(
+        FreeRoom,
+        "select r.id, r.name, min(c.start_time) filter (where c.start_time >= $2) as busy_at
+         from rooms as r
+         left join courses_rooms as cr on cr.room_id = r.id
+         left join courses as c on c.id = cr.course_id
+         where $3::text is null or starts_with(upper(r.name), upper($3))
+         group by r.id
+         having not coalesce(
+             bool_or(c.start_time < $2 and course_end(c, $4, $5) > $1),
+             false
+         )
+         order by r.name",
+        start,
+        end,
+        building,
+        json!(durations.categories),
+        durations.default,
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -1,35 +1,144 @@
+use std::fmt::Display;
+
+use serde_json::json;
 use thiserror::Error;
+use tracing::error;
 
+/// Errors returned to the clients.
+///
+/// The codes are stable, and grouped by kind:
+///
+/// | Codes | Kind               |
+/// |-------|--------------------|
+/// | 1xx   | authentication     |
+/// | 2xx   | validation         |
+/// | 3xx   | not found          |
+/// | 4xx   | forbidden          |
+/// | 5xx   | rate limited       |
+/// | 9xx   | internal           |
+///
+/// Some errors carry additional information in the `data` field of the JSON-RPC error.
 #[derive(Error, Debug, Clone)]
 pub enum RpcError {
-    #[error("unknown error")]
-    UnknownError = 0,
-
+    /// 100
     #[error("incorrect login information")]
-    IncorrectLoginInfo = 1,
+    IncorrectLoginInfo,
 
-    #[error("already registered")]
-    AlreadyRegistered = 3,
+    /// 101
+    #[error("not logged in")]
+    NotLogged,
+
+    /// 102, for registration and password reset tokens
+    #[error("the token is invalid or was already used")]
+    InvalidToken,
 
-    #[error("the token was already used")]
-    RegistrationTokenUsed = 4,
+    /// 103
+    #[error("this account is disabled")]
+    Disabled,
+
+    /// 200, data: `{ "field": string, "reasons": [string] }`
+    #[error("invalid {field}")]
+    InvalidField { field: String, reasons: Vec<String> },
+
+    /// 201
+    #[error("already registered")]
+    AlreadyRegistered,
 
+    /// 300
     #[error("the department supplied is unknown")]
-    UnknownDepartment = 5,
+    UnknownDepartment,
 
+    /// 301
     #[error("the client supplied is unknown")]
-    UnknownClient = 6,
+    UnknownClient,
+
+    /// 302
+    #[error("the session supplied is unknown")]
+    UnknownSession,
+
+    /// 303
+    #[error("the group supplied is unknown")]
+    UnknownGroup,
+
+    /// 304
+    #[error("the user supplied is unknown")]
+    UnknownUser,
+
+    /// 305
+    #[error("the teacher supplied is unknown")]
+    UnknownTeacher,
+
+    /// 306
+    #[error("the room supplied is unknown")]
+    UnknownRoom,
+
+    /// 400
+    #[error("forbidden")]
+    Forbidden,
 
-    #[error("unimplemented")]
-    Unimplemented = 2,
+    /// 500, data: `{ "retry_after": seconds }`
+    #[error("too many requests")]
+    RateLimited { retry_after: u64 },
+
+    /// 900, data: `{ "correlation_id": string }`, to be found in the logs
+    #[error("internal error")]
+    Internal { correlation_id: String },
+}
+
+impl RpcError {
+    /// Log an internal error and hide it behind a correlation ID
+    pub fn internal(err: impl Display) -> Self {
+        let correlation_id = uuid::Uuid::new_v4().to_string();
+        error!(%correlation_id, "{}", err);
+        RpcError::Internal { correlation_id }
+    }
+
+    pub fn invalid_field(field: &str, reason: &str) -> Self {
+        RpcError::InvalidField {
+            field: field.to_owned(),
+            reasons: vec![reason.to_owned()],
+        }
+    }
+
+    pub fn code(&self) -> i64 {
+        use RpcError::*;
+        match self {
+            IncorrectLoginInfo => 100,
+            NotLogged => 101,
+            InvalidToken => 102,
+            Disabled => 103,
+            InvalidField { .. } => 200,
+            AlreadyRegistered => 201,
+            UnknownDepartment => 300,
+            UnknownClient => 301,
+            UnknownSession => 302,
+            UnknownGroup => 303,
+            UnknownUser => 304,
+            UnknownTeacher => 305,
+            UnknownRoom => 306,
+            Forbidden => 400,
+            RateLimited { .. } => 500,
+            Internal { .. } => 900,
+        }
+    }
+
+    fn data(&self) -> Option<serde_json::Value> {
+        use RpcError::*;
+        match self {
+            InvalidField { field, reasons } => Some(json!({ "field": field, "reasons": reasons })),
+            RateLimited { retry_after } => Some(json!({ "retry_after": retry_after })),
+            Internal { correlation_id } => Some(json!({ "correlation_id": correlation_id })),
+            _ => None,
+        }
+    }
 }
 
 impl From<RpcError> for jsonrpc_core::Error {
     fn from(r: RpcError) -> Self {
         Self {
-            code: jsonrpc_core::ErrorCode::ServerError(r.clone() as i64),
+            code: jsonrpc_core::ErrorCode::ServerError(r.code()),
             message: r.to_string(),
-            data: None, // TODO: enum data
+            data: r.data(),
         }
     }
 }
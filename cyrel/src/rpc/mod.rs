@@ -1,26 +1,28 @@
-use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
-};
+use std::sync::Arc;
 
-use chrono::{NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use jsonrpc_core::BoxFuture;
 use jsonrpc_derive::rpc;
-use lettre::{
-    transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport,
-    Tokio1Executor,
-};
-use pbkdf2::{
-    password_hash::{PasswordHash, PasswordVerifier},
-    Pbkdf2,
-};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
 use sqlx::PgPool;
-use tracing::{debug, error, info, warn};
+use tracing::{info, warn};
 
-use crate::authentication::{self, Claims, Meta};
+use crate::authentication::{self, Meta, Verification};
+use crate::calendar;
 use crate::email;
-use crate::models::{Department, Group, Identity, User};
-use crate::schedule::Course;
+use crate::groups;
+use crate::limits::Limiter;
+use crate::models::{Account, Group, Identity, User};
+use crate::notifications::{self, Preferences};
+use crate::password;
+use crate::permissions::{self, Permission};
+use crate::rooms::{self, FreeRoom, Room};
+use crate::schedule::{self, Course, Page, UserCourse};
+use crate::sessions::{self, Session, SessionTokens};
+use crate::sync;
+use crate::teachers::{self, Teacher};
+use crate::timestamp::Timestamp;
+use crate::tokens::{self, Purpose};
 use crate::SETTINGS;
 
 pub use self::error::RpcError;
@@ -38,12 +40,34 @@ pub trait Rpc {
     #[rpc(name = "time")]
     fn time(&self) -> jsonrpc_core::Result<NaiveDateTime>;
 
-    #[rpc(name = "login", params = "named")]
-    fn login(&self, email: String, password: String) -> BoxFuture<jsonrpc_core::Result<String>>;
+    #[rpc(meta, name = "login", params = "named")]
+    fn login(
+        &self,
+        meta: Self::Metadata,
+        email: String,
+        password: String,
+    ) -> BoxFuture<jsonrpc_core::Result<SessionTokens>>;
+
+    #[rpc(name = "refresh", params = "named")]
+    fn refresh(&self, refresh: String) -> BoxFuture<jsonrpc_core::Result<SessionTokens>>;
+
+    #[rpc(meta, name = "logout", params = "named")]
+    fn logout(&self, meta: Self::Metadata) -> BoxFuture<jsonrpc_core::Result<String>>;
+
+    #[rpc(meta, name = "sessions_list", params = "named")]
+    fn sessions_list(&self, meta: Self::Metadata) -> BoxFuture<jsonrpc_core::Result<Vec<Session>>>;
+
+    #[rpc(meta, name = "sessions_revoke", params = "named")]
+    fn sessions_revoke(
+        &self,
+        meta: Self::Metadata,
+        session: String,
+    ) -> BoxFuture<jsonrpc_core::Result<String>>;
 
-    #[rpc(name = "register_1", params = "named")]
+    #[rpc(meta, name = "register_1", params = "named")]
     fn register_1(
         &self,
+        meta: Self::Metadata,
         ldap: i64,
         department: String,
         email: String,
@@ -81,11 +105,90 @@ pub trait Rpc {
     fn schedule_get(
         &self,
         meta: Self::Metadata,
-        start: NaiveDateTime,
-        end: NaiveDateTime,
+        start: Timestamp,
+        end: Timestamp,
         group: i32,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> BoxFuture<jsonrpc_core::Result<Vec<Course>>>;
+
+    #[rpc(meta, name = "my_schedule_get", params = "named")]
+    fn my_schedule_get(
+        &self,
+        meta: Self::Metadata,
+        start: Timestamp,
+        end: Timestamp,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> BoxFuture<jsonrpc_core::Result<Vec<UserCourse>>>;
+
+    #[rpc(meta, name = "rooms_free", params = "named")]
+    fn rooms_free(
+        &self,
+        meta: Self::Metadata,
+        start: Timestamp,
+        end: Timestamp,
+        building: Option<String>,
+    ) -> BoxFuture<jsonrpc_core::Result<Vec<FreeRoom>>>;
+
+    #[rpc(meta, name = "rooms_search", params = "named")]
+    fn rooms_search(
+        &self,
+        meta: Self::Metadata,
+        query: String,
+    ) -> BoxFuture<jsonrpc_core::Result<Vec<Room>>>;
+
+    #[rpc(meta, name = "room_schedule_get", params = "named")]
+    fn room_schedule_get(
+        &self,
+        meta: Self::Metadata,
+        start: Timestamp,
+        end: Timestamp,
+        room: i32,
+    ) -> BoxFuture<jsonrpc_core::Result<Vec<Course>>>;
+
+    #[rpc(meta, name = "teachers_search", params = "named")]
+    fn teachers_search(
+        &self,
+        meta: Self::Metadata,
+        query: String,
+    ) -> BoxFuture<jsonrpc_core::Result<Vec<Teacher>>>;
+
+    #[rpc(meta, name = "teacher_schedule_get", params = "named")]
+    fn teacher_schedule_get(
+        &self,
+        meta: Self::Metadata,
+        start: Timestamp,
+        end: Timestamp,
+        teacher: i32,
     ) -> BoxFuture<jsonrpc_core::Result<Vec<Course>>>;
 
+    #[rpc(meta, name = "sync_status", params = "named")]
+    fn sync_status(&self, meta: Self::Metadata) -> BoxFuture<jsonrpc_core::Result<sync::Status>>;
+
+    #[rpc(meta, name = "calendar_token_get", params = "named")]
+    fn calendar_token_get(&self, meta: Self::Metadata) -> BoxFuture<jsonrpc_core::Result<String>>;
+
+    #[rpc(meta, name = "calendar_token_revoke", params = "named")]
+    fn calendar_token_revoke(
+        &self,
+        meta: Self::Metadata,
+    ) -> BoxFuture<jsonrpc_core::Result<String>>;
+
+    #[rpc(meta, name = "notifications_get", params = "named")]
+    fn notifications_get(
+        &self,
+        meta: Self::Metadata,
+    ) -> BoxFuture<jsonrpc_core::Result<Preferences>>;
+
+    #[rpc(meta, name = "notifications_set", params = "named")]
+    fn notifications_set(
+        &self,
+        meta: Self::Metadata,
+        enabled: bool,
+        days: i32,
+    ) -> BoxFuture<jsonrpc_core::Result<String>>;
+
     #[rpc(meta, name = "client_configs_get", params = "named")]
     fn client_configs_get(
         &self,
@@ -101,9 +204,10 @@ pub trait Rpc {
         config: String,
     ) -> BoxFuture<jsonrpc_core::Result<String>>;
 
-    #[rpc(name = "send_password_reset_code", params = "named")]
+    #[rpc(meta, name = "send_password_reset_code", params = "named")]
     fn send_password_reset_code(
         &self,
+        meta: Self::Metadata,
         ldap: i64,
         email: String,
     ) -> BoxFuture<jsonrpc_core::Result<String>>;
@@ -114,30 +218,108 @@ pub trait Rpc {
         code: String,
         password: String,
     ) -> BoxFuture<jsonrpc_core::Result<String>>;
+
+    #[rpc(meta, name = "admin_groups_create", params = "named")]
+    fn admin_groups_create(
+        &self,
+        meta: Self::Metadata,
+        name: String,
+        private: bool,
+    ) -> BoxFuture<jsonrpc_core::Result<Group>>;
+
+    #[rpc(meta, name = "admin_groups_edit", params = "named")]
+    fn admin_groups_edit(
+        &self,
+        meta: Self::Metadata,
+        id: i32,
+        name: String,
+        private: bool,
+    ) -> BoxFuture<jsonrpc_core::Result<String>>;
+
+    #[rpc(meta, name = "admin_groups_delete", params = "named")]
+    fn admin_groups_delete(
+        &self,
+        meta: Self::Metadata,
+        id: i32,
+    ) -> BoxFuture<jsonrpc_core::Result<String>>;
+
+    #[rpc(meta, name = "admin_groups_referent_set", params = "named")]
+    fn admin_groups_referent_set(
+        &self,
+        meta: Self::Metadata,
+        id: i32,
+        referent: Option<i64>,
+    ) -> BoxFuture<jsonrpc_core::Result<String>>;
+
+    #[rpc(meta, name = "admin_groups_parent_set", params = "named")]
+    fn admin_groups_parent_set(
+        &self,
+        meta: Self::Metadata,
+        id: i32,
+        parent: Option<i32>,
+    ) -> BoxFuture<jsonrpc_core::Result<String>>;
+
+    #[rpc(meta, name = "admin_departments_create", params = "named")]
+    fn admin_departments_create(
+        &self,
+        meta: Self::Metadata,
+        id: String,
+        name: String,
+        domain: String,
+    ) -> BoxFuture<jsonrpc_core::Result<String>>;
+
+    #[rpc(meta, name = "admin_departments_edit", params = "named")]
+    fn admin_departments_edit(
+        &self,
+        meta: Self::Metadata,
+        id: String,
+        name: String,
+        domain: String,
+    ) -> BoxFuture<jsonrpc_core::Result<String>>;
+
+    #[rpc(meta, name = "admin_departments_delete", params = "named")]
+    fn admin_departments_delete(
+        &self,
+        meta: Self::Metadata,
+        id: String,
+    ) -> BoxFuture<jsonrpc_core::Result<String>>;
+
+    #[rpc(meta, name = "admin_clients_register", params = "named")]
+    fn admin_clients_register(
+        &self,
+        meta: Self::Metadata,
+        name: String,
+    ) -> BoxFuture<jsonrpc_core::Result<i32>>;
+
+    #[rpc(meta, name = "admin_users_list", params = "named")]
+    fn admin_users_list(
+        &self,
+        meta: Self::Metadata,
+    ) -> BoxFuture<jsonrpc_core::Result<Vec<Account>>>;
+
+    #[rpc(meta, name = "admin_users_disable", params = "named")]
+    fn admin_users_disable(
+        &self,
+        meta: Self::Metadata,
+        id: i64,
+        disabled: bool,
+    ) -> BoxFuture<jsonrpc_core::Result<String>>;
 }
 
 pub struct RpcImpl(Arc<RpcState>);
 
 struct RpcState {
     db: PgPool,
-    // FIXME: put those in the DB
-    new_users_tokens: Mutex<HashMap<String, User>>,
-    reset_password_tokens: Mutex<HashMap<String, User>>,
     mailer: AsyncSmtpTransport<Tokio1Executor>,
+    limiter: Limiter,
 }
 
 impl RpcImpl {
     pub fn new(db: PgPool) -> Result<RpcImpl, lettre::transport::smtp::Error> {
         Ok(RpcImpl(Arc::new(RpcState {
             db,
-            new_users_tokens: Mutex::new(HashMap::new()),
-            reset_password_tokens: Mutex::new(HashMap::new()),
-            mailer: AsyncSmtpTransport::<Tokio1Executor>::relay(&SETTINGS.smtp.server)?
-                .credentials(Credentials::new(
-                    SETTINGS.smtp.username.clone(),
-                    SETTINGS.smtp.password.clone(),
-                ))
-                .build(),
+            mailer: email::mailer()?,
+            limiter: Limiter::default(),
         })))
     }
 }
@@ -146,19 +328,61 @@ macro_rules! server_error {
     ($e:expr) => {
         match $e {
             Ok(a) => a,
-            Err(err) => {
-                error!("{}", err);
-                return Err(jsonrpc_core::Error {
-                    // TODO: define error codes
-                    code: jsonrpc_core::ErrorCode::ServerError(-32000),
-                    message: format!("{}", err),
-                    data: None,
-                });
-            }
+            Err(err) => return Err(RpcError::internal(err).into()),
         }
     };
 }
 
+/// Get the logged user, if their role grants `permission`
+async fn admin_get(db: &PgPool, meta: Meta, permission: Permission) -> jsonrpc_core::Result<User> {
+    let user = match server_error!(authentication::logged_user_get(db, meta).await) {
+        Some(user) => user,
+        None => return Err(RpcError::NotLogged.into()),
+    };
+
+    if server_error!(permissions::has(db, &user, permission).await) {
+        Ok(user)
+    } else {
+        warn!(
+            "{} isn't allowed to manage {}",
+            user.id,
+            permission.as_str()
+        );
+        Err(RpcError::Forbidden.into())
+    }
+}
+
+/// Times given in the local time of the campus are converted to UTC
+fn range(start: Timestamp, end: Timestamp) -> Result<(DateTime<Utc>, DateTime<Utc>), RpcError> {
+    let tz = SETTINGS.schedule.timezone;
+    let resolve = |field, time: Timestamp| {
+        time.resolve(tz)
+            .map_err(|_| RpcError::invalid_field(field, "doesn't exist in the local time"))
+    };
+    Ok((resolve("start", start)?, resolve("end", end)?))
+}
+
+/// Page of courses, at most `SETTINGS.schedule.limit` of them
+fn page(limit: Option<i64>, offset: Option<i64>) -> Result<Page, RpcError> {
+    let limit = limit.unwrap_or(SETTINGS.schedule.limit);
+    let offset = offset.unwrap_or(0);
+    if limit < 0 {
+        return Err(RpcError::invalid_field("limit", "must be positive"));
+    }
+    if offset < 0 {
+        return Err(RpcError::invalid_field("offset", "must be positive"));
+    }
+    Ok(Page {
+        limit: Some(limit.min(SETTINGS.schedule.limit)),
+        offset,
+    })
+}
+
+/// Whether `err` comes from the violation of `constraint`
+fn violates(err: &sqlx::Error, constraint: &str) -> bool {
+    matches!(err, sqlx::Error::Database(e) if e.constraint() == Some(constraint))
+}
+
 impl Rpc for RpcImpl {
     type Metadata = Meta;
 
@@ -172,9 +396,17 @@ impl Rpc for RpcImpl {
         Ok(Utc::now().naive_utc())
     }
 
-    fn login(&self, email: String, password: String) -> BoxFuture<jsonrpc_core::Result<String>> {
+    fn login(
+        &self,
+        meta: Self::Metadata,
+        email: String,
+        password: String,
+    ) -> BoxFuture<jsonrpc_core::Result<SessionTokens>> {
         let state = Arc::clone(&self.0);
         Box::pin(async move {
+            state.limiter.hit_ip(&meta)?;
+            state.limiter.check(&email)?;
+
             let user: User = match server_error! {
                 sqlx::query_as!(User, "select * from users where email = $1", email)
                     .fetch_optional(&state.db)
@@ -183,46 +415,134 @@ impl Rpc for RpcImpl {
                 Some(user) => user,
                 None => {
                     warn!("{} isn't a know email", email);
+                    state.limiter.fail(&email);
                     return Err(RpcError::IncorrectLoginInfo.into());
                 }
             };
-            let hash = server_error! {
-                PasswordHash::new(&user.password)
-            };
-            if Pbkdf2.verify_password(password.as_bytes(), &hash).is_ok() {
-                let jwt = server_error! {
-                    Claims::from_user(&user).to_jwt(&SETTINGS.jwt.secret)
-                };
-                info!("{} logged in", user.id);
-                Ok(jwt)
-            } else {
-                warn!("{} failed to log in", user.id);
-                Err(RpcError::IncorrectLoginInfo.into())
+            match server_error! {
+                authentication::verify_password(&password, &user.password)
+            } {
+                Verification::Invalid => {
+                    warn!("{} failed to log in", user.id);
+                    state.limiter.fail(&email);
+                    return Err(RpcError::IncorrectLoginInfo.into());
+                }
+                Verification::Valid => {}
+                Verification::Outdated => {
+                    let hash = server_error!(authentication::hash_password(&password));
+                    server_error! {
+                        sqlx::query!(
+                            "update users set password = $1 where id = $2",
+                            hash, user.id,
+                        ).execute(&state.db).await
+                    };
+                    info!("{} password was hashed again", user.id);
+                }
+            }
+
+            if user.disabled {
+                warn!("{} is disabled", user.id);
+                return Err(RpcError::Disabled.into());
+            }
+            let tokens = server_error!(sessions::open(&state.db, user.id).await);
+            state.limiter.succeed(&email);
+            info!("{} logged in", user.id);
+            Ok(tokens)
+        })
+    }
+
+    fn refresh(&self, refresh: String) -> BoxFuture<jsonrpc_core::Result<SessionTokens>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            match server_error!(sessions::refresh(&state.db, &refresh).await) {
+                Some(tokens) => Ok(tokens),
+                None => {
+                    warn!(
+                        "Someone tried to use an used or inexistant refresh token: {}",
+                        tokens::redact(&refresh)
+                    );
+                    Err(RpcError::InvalidToken.into())
+                }
+            }
+        })
+    }
+
+    fn logout(&self, meta: Self::Metadata) -> BoxFuture<jsonrpc_core::Result<String>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            match server_error! {
+                authentication::logged_session_get(&state.db, meta).await
+            } {
+                Some((user, session)) => {
+                    server_error!(sessions::close(&state.db, user.id, &session).await);
+                    info!("{} logged out", user.id);
+                    Ok("Success!".to_string())
+                }
+                None => Err(RpcError::NotLogged.into()),
+            }
+        })
+    }
+
+    fn sessions_list(&self, meta: Self::Metadata) -> BoxFuture<jsonrpc_core::Result<Vec<Session>>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            match server_error! {
+                authentication::logged_session_get(&state.db, meta).await
+            } {
+                Some((user, session)) => Ok(server_error!(
+                    sessions::list(&state.db, user.id, &session).await
+                )),
+                None => Err(RpcError::NotLogged.into()),
+            }
+        })
+    }
+
+    fn sessions_revoke(
+        &self,
+        meta: Self::Metadata,
+        session: String,
+    ) -> BoxFuture<jsonrpc_core::Result<String>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            match server_error! {
+                authentication::logged_user_get(&state.db, meta).await
+            } {
+                Some(user) => {
+                    if server_error!(sessions::close(&state.db, user.id, &session).await) {
+                        Ok("Success!".to_string())
+                    } else {
+                        Err(RpcError::UnknownSession.into())
+                    }
+                }
+                None => Err(RpcError::NotLogged.into()),
             }
         })
     }
 
     fn register_1(
         &self,
+        meta: Self::Metadata,
         ldap: i64,
         department: String,
         email: String,
     ) -> BoxFuture<jsonrpc_core::Result<String>> {
         let state = Arc::clone(&self.0);
         Box::pin(async move {
-            let dpmt: Department = match server_error! {
-                sqlx::query_as!(Department, "select * from departments where id = $1", department)
+            state.limiter.hit_ip(&meta)?;
+
+            let domain = match server_error! {
+                sqlx::query_scalar!("select domain from departments where id = $1", department)
                     .fetch_optional(&state.db)
                     .await
             } {
-                Some(dpmt) => dpmt,
+                Some(domain) => domain,
                 None => {
                     warn!("department {} is unknown", department);
                     return Err(RpcError::UnknownDepartment.into());
                 }
             };
 
-            let email = format!("{}@{}", email, &dpmt.domain);
+            let email = format!("{}@{}", email, domain);
 
             if server_error!(
                 sqlx::query!("select id from users where id = $1", ldap)
@@ -235,16 +555,13 @@ impl Rpc for RpcImpl {
                 return Err(RpcError::AlreadyRegistered.into());
             }
 
-            match server_error! {
+            if let Some(x) = server_error! {
                 sqlx::query!("select id from users where email = $1", email)
                     .fetch_optional(&state.db)
                     .await
             } {
-                Some(x) => {
-                    warn!("email {} is already used for user {}", email, x.id);
-                    return Err(RpcError::AlreadyRegistered.into());
-                }
-                None => {}
+                warn!("email {} is already used for user {}", email, x.id);
+                return Err(RpcError::AlreadyRegistered.into());
             }
 
             let (firstname, lastname) = match server_error! {
@@ -260,53 +577,44 @@ impl Rpc for RpcImpl {
                 }
             };
 
-            let hash = uuid::Uuid::new_v4().to_string();
-            debug!("hash: {}", hash);
-
             let user = User {
                 id: ldap,
                 firstname,
                 lastname,
                 email: email.clone(),
                 password: "".to_string(),
+                role: None,
+                disabled: false,
             };
-            state
-                .new_users_tokens
-                .lock()
-                .unwrap()
-                .insert(hash.clone(), user);
-
-            let message = match email::gen_inscription(&email, &hash) {
-                Ok(msg) => msg,
-                Err(err) => {
-                    warn!("{}", err);
-                    return Err(RpcError::UnknownError.into());
-                }
+
+            state.limiter.hit_email(&email)?;
+            let hash = server_error! {
+                tokens::create(&state.db, Purpose::Registration, &user).await
             };
-            match state.mailer.send(message).await {
-                Ok(_) => Ok("Code sent".to_string()),
-                Err(err) => {
-                    warn!("{}", err);
-                    Err(RpcError::UnknownError.into())
-                }
-            }
+
+            let message = server_error!(email::gen_inscription(&email, &hash));
+            server_error!(state.mailer.send(message).await);
+
+            Ok("Code sent".to_string())
         })
     }
 
     fn register_2(&self, hash: String) -> BoxFuture<jsonrpc_core::Result<Identity>> {
         let state = Arc::clone(&self.0);
         Box::pin(async move {
-            match state.new_users_tokens.lock().unwrap().get(&hash) {
+            match server_error! {
+                tokens::peek(&state.db, Purpose::Registration, &hash).await
+            } {
                 Some(user) => Ok(Identity {
-                    firstname: user.firstname.clone(),
-                    lastname: user.lastname.clone(),
+                    firstname: user.firstname,
+                    lastname: user.lastname,
                 }),
                 None => {
                     warn!(
                         "Someone tried to use an used or inexistant token: {}",
-                        hash.to_owned()
+                        tokens::redact(&hash)
                     );
-                    Err(RpcError::RegistrationTokenUsed.into())
+                    Err(RpcError::InvalidToken.into())
                 }
             }
         })
@@ -321,25 +629,29 @@ impl Rpc for RpcImpl {
     ) -> BoxFuture<jsonrpc_core::Result<String>> {
         let state = Arc::clone(&self.0);
         Box::pin(async move {
-            let user = match state.new_users_tokens.lock().unwrap().remove(&hash) {
-                Some(u) => User {
-                    firstname,
-                    lastname,
-                    password: match authentication::hash_password(&password, &u.id.to_string()) {
-                        Ok(p) => p,
-                        Err(err) => {
-                            warn!("{}", err);
-                            return Err(RpcError::UnknownError.into());
-                        }
-                    },
-                    ..u
-                },
+            let mut tx = server_error!(state.db.begin().await);
+
+            let user = match server_error! {
+                tokens::consume(&mut tx, Purpose::Registration, &hash).await
+            } {
+                Some(u) => {
+                    let u = User {
+                        firstname,
+                        lastname,
+                        ..u
+                    };
+                    password::check(&password, &u)?;
+                    User {
+                        password: server_error!(authentication::hash_password(&password)),
+                        ..u
+                    }
+                }
                 None => {
                     warn!(
                         "Someone tried to use an used or inexistant token: {}",
-                        hash.to_owned()
+                        tokens::redact(&hash)
                     );
-                    return Err(RpcError::RegistrationTokenUsed.into());
+                    return Err(RpcError::InvalidToken.into());
                 }
             };
 
@@ -348,9 +660,11 @@ impl Rpc for RpcImpl {
                     "insert into users (id, firstname, lastname, email, password)
                      values ($1, $2, $3, $4, $5)",
                     user.id, user.firstname, user.lastname, user.email, user.password,
-                ).execute(&state.db).await
+                ).execute(&mut tx).await
             };
 
+            server_error!(tx.commit().await);
+
             Ok("Account created!".to_string())
         })
     }
@@ -377,7 +691,7 @@ impl Rpc for RpcImpl {
                         user.id,
                     ).fetch_all(&state.db).await
                 }),
-                None => Err(RpcError::IncorrectLoginInfo.into()),
+                None => Err(RpcError::NotLogged.into()),
             }
         })
     }
@@ -392,7 +706,7 @@ impl Rpc for RpcImpl {
                     sqlx::query_as!(Group, "select * from groups where private = false")
                         .fetch_all(&state.db).await
                 }),
-                None => Err(RpcError::IncorrectLoginInfo.into()),
+                None => Err(RpcError::NotLogged.into()),
             }
         })
     }
@@ -421,7 +735,7 @@ impl Rpc for RpcImpl {
                     }
                     Ok("Success!".to_string())
                 }
-                None => Err(RpcError::IncorrectLoginInfo.into()),
+                None => Err(RpcError::NotLogged.into()),
             }
         })
     }
@@ -429,40 +743,301 @@ impl Rpc for RpcImpl {
     fn schedule_get(
         &self,
         meta: Self::Metadata,
-        start: NaiveDateTime,
-        end: NaiveDateTime,
+        start: Timestamp,
+        end: Timestamp,
         group: i32,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> BoxFuture<jsonrpc_core::Result<Vec<Course>>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let (start, end) = range(start, end)?;
+            match server_error! {
+                authentication::logged_user_get(&state.db, meta).await
+            } {
+                Some(user) => {
+                    let page = page(limit, offset)?;
+                    if server_error!(schedule::can_see(&state.db, user.id, group).await) {
+                        Ok(server_error!(
+                            schedule::get(
+                                &state.db,
+                                &SETTINGS.schedule.durations,
+                                group,
+                                start,
+                                end,
+                                page,
+                            )
+                            .await
+                        ))
+                    } else {
+                        Err(RpcError::Forbidden.into())
+                    }
+                }
+                None => Err(RpcError::NotLogged.into()),
+            }
+        })
+    }
+
+    fn my_schedule_get(
+        &self,
+        meta: Self::Metadata,
+        start: Timestamp,
+        end: Timestamp,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> BoxFuture<jsonrpc_core::Result<Vec<UserCourse>>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let (start, end) = range(start, end)?;
+            match server_error! {
+                authentication::logged_user_get(&state.db, meta).await
+            } {
+                Some(user) => {
+                    let page = page(limit, offset)?;
+                    Ok(server_error!(
+                        schedule::get_for_user(
+                            &state.db,
+                            &SETTINGS.schedule.durations,
+                            user.id,
+                            start,
+                            end,
+                            page,
+                        )
+                        .await
+                    ))
+                }
+                None => Err(RpcError::NotLogged.into()),
+            }
+        })
+    }
+
+    fn rooms_free(
+        &self,
+        meta: Self::Metadata,
+        start: Timestamp,
+        end: Timestamp,
+        building: Option<String>,
+    ) -> BoxFuture<jsonrpc_core::Result<Vec<FreeRoom>>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let (start, end) = range(start, end)?;
+            match server_error! {
+                authentication::logged_user_get(&state.db, meta).await
+            } {
+                Some(_) => {
+                    if end <= start {
+                        return Err(RpcError::invalid_field("end", "must be after start").into());
+                    }
+                    Ok(server_error!(
+                        rooms::free(
+                            &state.db,
+                            &SETTINGS.schedule.durations,
+                            start,
+                            end,
+                            building.as_deref(),
+                        )
+                        .await
+                    ))
+                }
+                None => Err(RpcError::NotLogged.into()),
+            }
+        })
+    }
+
+    fn rooms_search(
+        &self,
+        meta: Self::Metadata,
+        query: String,
+    ) -> BoxFuture<jsonrpc_core::Result<Vec<Room>>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            match server_error! {
+                authentication::logged_user_get(&state.db, meta).await
+            } {
+                Some(_) => Ok(server_error!(rooms::search(&state.db, &query).await)),
+                None => Err(RpcError::NotLogged.into()),
+            }
+        })
+    }
+
+    fn room_schedule_get(
+        &self,
+        meta: Self::Metadata,
+        start: Timestamp,
+        end: Timestamp,
+        room: i32,
     ) -> BoxFuture<jsonrpc_core::Result<Vec<Course>>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let (start, end) = range(start, end)?;
+            match server_error! {
+                authentication::logged_user_get(&state.db, meta).await
+            } {
+                Some(user) => {
+                    if server_error!(rooms::exists(&state.db, room).await) {
+                        Ok(server_error!(
+                            rooms::schedule(
+                                &state.db,
+                                &SETTINGS.schedule.durations,
+                                user.id,
+                                room,
+                                start,
+                                end,
+                            )
+                            .await
+                        ))
+                    } else {
+                        Err(RpcError::UnknownRoom.into())
+                    }
+                }
+                None => Err(RpcError::NotLogged.into()),
+            }
+        })
+    }
+
+    fn teachers_search(
+        &self,
+        meta: Self::Metadata,
+        query: String,
+    ) -> BoxFuture<jsonrpc_core::Result<Vec<Teacher>>> {
         let state = Arc::clone(&self.0);
         Box::pin(async move {
             match server_error! {
                 authentication::logged_user_get(&state.db, meta).await
             } {
-                Some(user) => match server_error! {
-                    sqlx::query!(
-                        "select from groups as g
-                         join groups as h on h.id = g.id or h.parent = g.parent
-                         join users_groups as ug on ug.group_id = h.id
-                         where ug.user_id = $1 and g.id = $2",
-                        user.id, group,
-                    ).fetch_optional(&state.db).await
-                } {
-                    Some(_) => Ok(server_error! {
-                        sqlx::query_as!(
-                            Course,
-                            "select c.* from courses as c
-                             join groups_courses as gc on c.id = gc.course_id
-                             where gc.group_id = $1 and c.start_time >= $2 and c.end_time <= $3",
-                            group, start, end,
-                        ).fetch_all(&state.db).await
-                    }),
-                    None => Err(RpcError::Unimplemented.into()),
-                },
-                None => Err(RpcError::IncorrectLoginInfo.into()),
+                Some(_) => Ok(server_error!(teachers::search(&state.db, &query).await)),
+                None => Err(RpcError::NotLogged.into()),
             }
         })
     }
 
+    fn teacher_schedule_get(
+        &self,
+        meta: Self::Metadata,
+        start: Timestamp,
+        end: Timestamp,
+        teacher: i32,
+    ) -> BoxFuture<jsonrpc_core::Result<Vec<Course>>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let (start, end) = range(start, end)?;
+            match server_error! {
+                authentication::logged_user_get(&state.db, meta).await
+            } {
+                Some(user) => {
+                    if server_error!(teachers::exists(&state.db, teacher).await) {
+                        Ok(server_error!(
+                            teachers::schedule(
+                                &state.db,
+                                &SETTINGS.schedule.durations,
+                                user.id,
+                                teacher,
+                                start,
+                                end,
+                            )
+                            .await
+                        ))
+                    } else {
+                        Err(RpcError::UnknownTeacher.into())
+                    }
+                }
+                None => Err(RpcError::NotLogged.into()),
+            }
+        })
+    }
+
+    fn sync_status(&self, meta: Self::Metadata) -> BoxFuture<jsonrpc_core::Result<sync::Status>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let user = match server_error! {
+                authentication::logged_user_get(&state.db, meta).await
+            } {
+                Some(user) => user,
+                None => return Err(RpcError::NotLogged.into()),
+            };
+            let admin = server_error!(permissions::has(&state.db, &user, Permission::Groups).await);
+
+            Ok(server_error!(sync::status(&state.db, admin).await))
+        })
+    }
+
+    fn calendar_token_get(&self, meta: Self::Metadata) -> BoxFuture<jsonrpc_core::Result<String>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            match server_error! {
+                authentication::logged_user_get(&state.db, meta).await
+            } {
+                Some(user) => Ok(server_error!(calendar::token_get(&state.db, user.id).await)),
+                None => Err(RpcError::NotLogged.into()),
+            }
+        })
+    }
+
+    fn calendar_token_revoke(
+        &self,
+        meta: Self::Metadata,
+    ) -> BoxFuture<jsonrpc_core::Result<String>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            match server_error! {
+                authentication::logged_user_get(&state.db, meta).await
+            } {
+                Some(user) => {
+                    server_error!(calendar::token_revoke(&state.db, user.id).await);
+                    Ok("Success!".to_string())
+                }
+                None => Err(RpcError::NotLogged.into()),
+            }
+        })
+    }
+
+    fn notifications_get(
+        &self,
+        meta: Self::Metadata,
+    ) -> BoxFuture<jsonrpc_core::Result<Preferences>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            match server_error! {
+                authentication::logged_user_get(&state.db, meta).await
+            } {
+                Some(user) => Ok(server_error!(
+                    notifications::preferences_get(&state.db, user.id).await
+                )),
+                None => Err(RpcError::NotLogged.into()),
+            }
+        })
+    }
+
+    fn notifications_set(
+        &self,
+        meta: Self::Metadata,
+        enabled: bool,
+        days: i32,
+    ) -> BoxFuture<jsonrpc_core::Result<String>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let user = match server_error! {
+                authentication::logged_user_get(&state.db, meta).await
+            } {
+                Some(user) => user,
+                None => {
+                    return Err(RpcError::NotLogged.into());
+                }
+            };
+
+            if !(1..=31).contains(&days) {
+                return Err(RpcError::invalid_field("days", "must be between 1 and 31").into());
+            }
+
+            server_error!(
+                notifications::preferences_set(&state.db, user.id, &Preferences { enabled, days })
+                    .await
+            );
+
+            Ok("Success!".to_string())
+        })
+    }
+
     fn client_configs_get(
         &self,
         meta: Self::Metadata,
@@ -475,7 +1050,7 @@ impl Rpc for RpcImpl {
             } {
                 Some(user) => user,
                 None => {
-                    return Err(RpcError::IncorrectLoginInfo.into());
+                    return Err(RpcError::NotLogged.into());
                 }
             };
 
@@ -516,7 +1091,7 @@ impl Rpc for RpcImpl {
             } {
                 Some(user) => user,
                 None => {
-                    return Err(RpcError::IncorrectLoginInfo.into());
+                    return Err(RpcError::NotLogged.into());
                 }
             };
 
@@ -549,11 +1124,14 @@ impl Rpc for RpcImpl {
 
     fn send_password_reset_code(
         &self,
+        meta: Self::Metadata,
         ldap: i64,
         email: String,
     ) -> BoxFuture<jsonrpc_core::Result<String>> {
         let state = Arc::clone(&self.0);
         Box::pin(async move {
+            state.limiter.hit_ip(&meta)?;
+
             let user = match server_error! {
                 sqlx::query_as!(User, "select * from users where id = $1", ldap)
                     .fetch_optional(&state.db)
@@ -569,32 +1147,20 @@ impl Rpc for RpcImpl {
                 return Err(RpcError::IncorrectLoginInfo.into());
             }
 
-            let hash = uuid::Uuid::new_v4().to_string();
-            debug!("hash: {}", hash);
-
-            let firstname = user.firstname.clone();
-            let lastname = user.lastname.clone();
+            state.limiter.hit_email(&email)?;
+            let hash = server_error! {
+                tokens::create(&state.db, Purpose::Reset, &user).await
+            };
 
-            state
-                .reset_password_tokens
-                .lock()
-                .unwrap()
-                .insert(hash.clone(), user);
+            let message = server_error!(email::gen_reset(
+                &email,
+                &user.firstname,
+                &user.lastname,
+                &hash
+            ));
+            server_error!(state.mailer.send(message).await);
 
-            let message = match email::gen_reset(&email, &firstname, &lastname, &hash) {
-                Ok(msg) => msg,
-                Err(err) => {
-                    warn!("{}", err);
-                    return Err(RpcError::UnknownError.into());
-                }
-            };
-            match state.mailer.send(message).await {
-                Ok(_) => Ok("Code sent".to_string()),
-                Err(err) => {
-                    warn!("{}", err);
-                    Err(RpcError::UnknownError.into())
-                }
-            }
+            Ok("Code sent".to_string())
         })
     }
 
@@ -605,23 +1171,24 @@ impl Rpc for RpcImpl {
     ) -> BoxFuture<jsonrpc_core::Result<String>> {
         let state = Arc::clone(&self.0);
         Box::pin(async move {
-            let user = match state.reset_password_tokens.lock().unwrap().remove(&code) {
-                Some(u) => User {
-                    password: match authentication::hash_password(&password, &u.id.to_string()) {
-                        Ok(p) => p,
-                        Err(err) => {
-                            warn!("{}", err);
-                            return Err(RpcError::UnknownError.into());
-                        }
-                    },
-                    ..u
-                },
+            let mut tx = server_error!(state.db.begin().await);
+
+            let user = match server_error! {
+                tokens::consume(&mut tx, Purpose::Reset, &code).await
+            } {
+                Some(u) => {
+                    password::check(&password, &u)?;
+                    User {
+                        password: server_error!(authentication::hash_password(&password)),
+                        ..u
+                    }
+                }
                 None => {
                     warn!(
                         "Someone tried to use a used or inexistant token: {}",
-                        code.to_owned()
+                        tokens::redact(&code)
                     );
-                    return Err(RpcError::Unimplemented.into());
+                    return Err(RpcError::InvalidToken.into());
                 }
             };
 
@@ -629,10 +1196,348 @@ impl Rpc for RpcImpl {
                 sqlx::query!(
                     "update users set password = $1 where id = $2",
                     user.password, user.id,
-                ).execute(&state.db).await
+                ).execute(&mut tx).await
             };
 
+            let closed = server_error!(sessions::close_all(&mut tx, user.id).await);
+            info!(
+                "{} reset their password, closing {} sessions",
+                user.id, closed
+            );
+
+            server_error!(tx.commit().await);
+
             Ok("Password changed!".to_string())
         })
     }
+
+    fn admin_groups_create(
+        &self,
+        meta: Self::Metadata,
+        name: String,
+        private: bool,
+    ) -> BoxFuture<jsonrpc_core::Result<Group>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let admin = admin_get(&state.db, meta, Permission::Groups).await?;
+
+            let group = server_error! {
+                sqlx::query_as!(
+                    Group,
+                    "insert into groups (name, private) values ($1, $2) returning *",
+                    name, private,
+                ).fetch_one(&state.db).await
+            };
+            info!("{} created group {}", admin.id, group.id);
+
+            Ok(group)
+        })
+    }
+
+    fn admin_groups_edit(
+        &self,
+        meta: Self::Metadata,
+        id: i32,
+        name: String,
+        private: bool,
+    ) -> BoxFuture<jsonrpc_core::Result<String>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let admin = admin_get(&state.db, meta, Permission::Groups).await?;
+
+            let edited = server_error! {
+                sqlx::query!(
+                    "update groups set name = $2, private = $3 where id = $1",
+                    id, name, private,
+                ).execute(&state.db).await
+            }
+            .rows_affected();
+            if edited == 0 {
+                return Err(RpcError::UnknownGroup.into());
+            }
+            info!("{} edited group {}", admin.id, id);
+
+            Ok("Success!".to_string())
+        })
+    }
+
+    fn admin_groups_delete(
+        &self,
+        meta: Self::Metadata,
+        id: i32,
+    ) -> BoxFuture<jsonrpc_core::Result<String>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let admin = admin_get(&state.db, meta, Permission::Groups).await?;
+
+            if !server_error!(groups::delete(&state.db, id).await) {
+                return Err(RpcError::UnknownGroup.into());
+            }
+            info!("{} deleted group {}", admin.id, id);
+
+            Ok("Success!".to_string())
+        })
+    }
+
+    fn admin_groups_referent_set(
+        &self,
+        meta: Self::Metadata,
+        id: i32,
+        referent: Option<i64>,
+    ) -> BoxFuture<jsonrpc_core::Result<String>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let admin = admin_get(&state.db, meta, Permission::Groups).await?;
+
+            if let Some(referent) = referent {
+                if server_error! {
+                    sqlx::query!("select id from users where id = $1", referent)
+                        .fetch_optional(&state.db)
+                        .await
+                }
+                .is_none()
+                {
+                    return Err(RpcError::UnknownUser.into());
+                }
+            }
+
+            let edited = server_error! {
+                sqlx::query!(
+                    "update groups set referent = $2 where id = $1",
+                    id, referent,
+                ).execute(&state.db).await
+            }
+            .rows_affected();
+            if edited == 0 {
+                return Err(RpcError::UnknownGroup.into());
+            }
+            info!(
+                "{} set the referent of group {} to {:?}",
+                admin.id, id, referent
+            );
+
+            Ok("Success!".to_string())
+        })
+    }
+
+    fn admin_groups_parent_set(
+        &self,
+        meta: Self::Metadata,
+        id: i32,
+        parent: Option<i32>,
+    ) -> BoxFuture<jsonrpc_core::Result<String>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let admin = admin_get(&state.db, meta, Permission::Groups).await?;
+
+            if let Some(parent) = parent {
+                if server_error! {
+                    sqlx::query!("select id from groups where id = $1", parent)
+                        .fetch_optional(&state.db)
+                        .await
+                }
+                .is_none()
+                {
+                    return Err(RpcError::UnknownGroup.into());
+                }
+                if server_error!(groups::would_cycle(&state.db, id, parent).await) {
+                    return Err(RpcError::invalid_field(
+                        "parent",
+                        "a group can't be its own ancestor",
+                    )
+                    .into());
+                }
+            }
+
+            let edited = server_error! {
+                sqlx::query!(
+                    "update groups set parent = $2 where id = $1",
+                    id, parent,
+                ).execute(&state.db).await
+            }
+            .rows_affected();
+            if edited == 0 {
+                return Err(RpcError::UnknownGroup.into());
+            }
+            info!(
+                "{} set the parent of group {} to {:?}",
+                admin.id, id, parent
+            );
+
+            Ok("Success!".to_string())
+        })
+    }
+
+    fn admin_departments_create(
+        &self,
+        meta: Self::Metadata,
+        id: String,
+        name: String,
+        domain: String,
+    ) -> BoxFuture<jsonrpc_core::Result<String>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let admin = admin_get(&state.db, meta, Permission::Departments).await?;
+
+            let created = match sqlx::query!(
+                "insert into departments (id, name, domain) values ($1, $2, $3)
+                 on conflict (id) do nothing",
+                id,
+                name,
+                domain,
+            )
+            .execute(&state.db)
+            .await
+            {
+                Ok(res) => res.rows_affected(),
+                Err(err) if violates(&err, "departments_name_key") => {
+                    return Err(RpcError::invalid_field("name", "already used").into());
+                }
+                Err(err) => return Err(RpcError::internal(err).into()),
+            };
+            if created == 0 {
+                return Err(RpcError::invalid_field("id", "already used").into());
+            }
+            info!("{} created department {}", admin.id, id);
+
+            Ok("Success!".to_string())
+        })
+    }
+
+    fn admin_departments_edit(
+        &self,
+        meta: Self::Metadata,
+        id: String,
+        name: String,
+        domain: String,
+    ) -> BoxFuture<jsonrpc_core::Result<String>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let admin = admin_get(&state.db, meta, Permission::Departments).await?;
+
+            let edited = match sqlx::query!(
+                "update departments set name = $2, domain = $3 where id = $1",
+                id,
+                name,
+                domain,
+            )
+            .execute(&state.db)
+            .await
+            {
+                Ok(res) => res.rows_affected(),
+                Err(err) if violates(&err, "departments_name_key") => {
+                    return Err(RpcError::invalid_field("name", "already used").into());
+                }
+                Err(err) => return Err(RpcError::internal(err).into()),
+            };
+            if edited == 0 {
+                return Err(RpcError::UnknownDepartment.into());
+            }
+            info!("{} edited department {}", admin.id, id);
+
+            Ok("Success!".to_string())
+        })
+    }
+
+    fn admin_departments_delete(
+        &self,
+        meta: Self::Metadata,
+        id: String,
+    ) -> BoxFuture<jsonrpc_core::Result<String>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let admin = admin_get(&state.db, meta, Permission::Departments).await?;
+
+            let deleted = server_error! {
+                sqlx::query!("delete from departments where id = $1", id)
+                    .execute(&state.db)
+                    .await
+            }
+            .rows_affected();
+            if deleted == 0 {
+                return Err(RpcError::UnknownDepartment.into());
+            }
+            info!("{} deleted department {}", admin.id, id);
+
+            Ok("Success!".to_string())
+        })
+    }
+
+    fn admin_clients_register(
+        &self,
+        meta: Self::Metadata,
+        name: String,
+    ) -> BoxFuture<jsonrpc_core::Result<i32>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let admin = admin_get(&state.db, meta, Permission::Clients).await?;
+
+            let client = server_error! {
+                sqlx::query!("insert into clients (name) values ($1) returning id", name)
+                    .fetch_one(&state.db)
+                    .await
+            };
+            info!("{} registered client {}", admin.id, client.id);
+
+            Ok(client.id)
+        })
+    }
+
+    fn admin_users_list(
+        &self,
+        meta: Self::Metadata,
+    ) -> BoxFuture<jsonrpc_core::Result<Vec<Account>>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            admin_get(&state.db, meta, Permission::Users).await?;
+
+            Ok(server_error! {
+                sqlx::query_as!(
+                    Account,
+                    "select id, firstname, lastname, email, role, disabled from users
+                     order by lastname, firstname",
+                ).fetch_all(&state.db).await
+            })
+        })
+    }
+
+    fn admin_users_disable(
+        &self,
+        meta: Self::Metadata,
+        id: i64,
+        disabled: bool,
+    ) -> BoxFuture<jsonrpc_core::Result<String>> {
+        let state = Arc::clone(&self.0);
+        Box::pin(async move {
+            let admin = admin_get(&state.db, meta, Permission::Users).await?;
+
+            if admin.id == id {
+                return Err(RpcError::invalid_field("id", "you can't disable yourself").into());
+            }
+
+            let mut tx = server_error!(state.db.begin().await);
+
+            let edited = server_error! {
+                sqlx::query!(
+                    "update users set disabled = $2 where id = $1",
+                    id, disabled,
+                ).execute(&mut tx).await
+            }
+            .rows_affected();
+            if edited == 0 {
+                return Err(RpcError::UnknownUser.into());
+            }
+
+            if disabled {
+                let closed = server_error!(sessions::close_all(&mut tx, id).await);
+                info!("{} disabled {}, closing {} sessions", admin.id, id, closed);
+            } else {
+                info!("{} enabled {}", admin.id, id);
+            }
+
+            server_error!(tx.commit().await);
+
+            Ok("Success!".to_string())
+        })
+    }
 }
@@ -1,14 +1,19 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Utc};
 use serde::Serialize;
+use serde_json::json;
+use sqlx::{PgExecutor, PgPool};
+
+use crate::settings::Durations;
 
 #[derive(Debug, Serialize)]
 pub struct Course {
     /// Unique ID
     pub id: String,
     #[serde(rename = "start")]
-    pub start_time: NaiveDateTime,
+    pub start_time: DateTime<Utc>,
+    /// Given by the duration of the category for courses without an end
     #[serde(rename = "end")]
-    pub end_time: Option<NaiveDateTime>,
+    pub end_time: DateTime<Utc>,
     pub category: Option<String>,
 
     /// Subject being taught
@@ -19,3 +24,334 @@ pub struct Course {
     /// Any additional description
     pub description: Option<String>,
 }
+
+/// A course of the groups of a user
+#[derive(Debug, Serialize)]
+pub struct UserCourse {
+    #[serde(flatten)]
+    pub course: Course,
+    /// Groups of the user having this course
+    pub groups: Vec<i32>,
+}
+
+/// Whether `user` is allowed to see the schedule of `group`
+pub async fn can_see(pool: &PgPool, user: i64, group: i32) -> sqlx::Result<bool> {
+    sqlx::query_scalar!(r#"select can_see_group($1, $2) as "can_see!""#, user, group)
+        .fetch_one(pool)
+        .await
+}
+
+/// Courses to return among the matching ones, which are ordered by start
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Page {
+    /// Every course if `None`
+    pub limit: Option<i64>,
+    pub offset: i64,
+}
+
+/// Courses of `group` overlapping `start` to `end`.
+///
+/// Courses without an end are considered to last the duration of their category.
+pub async fn get<'c>(
+    executor: impl PgExecutor<'c>,
+    durations: &Durations,
+    group: i32,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    page: Page,
+) -> sqlx::Result<Vec<Course>> {
+    sqlx::query_as!(
+        Course,
+        r#"select c.id, c.start_time, course_end(c, $4, $5) as "end_time!", c.category,
+                  c.module, c.room, c.teacher, c.description
+           from courses as c
+           join groups_courses as gc on c.id = gc.course_id
+           where gc.group_id = $1 and c.start_time < $3
+             and course_end(c, $4, $5) > $2
+           order by c.start_time, c.id
+           limit $6 offset $7"#,
+        group,
+        start,
+        end,
+        json!(durations.categories),
+        durations.default,
+        page.limit,
+        page.offset,
+    )
+    .fetch_all(executor)
+    .await
+}
+
+/// Courses of every group of `user`, each course only once
+pub async fn get_for_user<'c>(
+    executor: impl PgExecutor<'c>,
+    durations: &Durations,
+    user: i64,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    page: Page,
+) -> sqlx::Result<Vec<UserCourse>> {
+    Ok(sqlx::query!(
+        r#"select c.id, c.start_time, course_end(c, $4, $5) as "end_time!", c.category,
+                  c.module, c.room, c.teacher, c.description,
+                  array_agg(gc.group_id order by gc.group_id) as "groups!"
+           from courses as c
+           join groups_courses as gc on c.id = gc.course_id
+           join users_groups as ug on ug.group_id = gc.group_id
+           where ug.user_id = $1 and c.start_time < $3
+             and course_end(c, $4, $5) > $2
+           group by c.id
+           order by c.start_time, c.id
+           limit $6 offset $7"#,
+        user,
+        start,
+        end,
+        json!(durations.categories),
+        durations.default,
+        page.limit,
+        page.offset,
+    )
+    .fetch_all(executor)
+    .await?
+    .into_iter()
+    .map(|c| UserCourse {
+        course: Course {
+            id: c.id,
+            start_time: c.start_time,
+            end_time: c.end_time,
+            category: c.category,
+            module: c.module,
+            room: c.room,
+            teacher: c.teacher,
+            description: c.description,
+        },
+        groups: c.groups,
+    })
+    .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use sqlx::{Postgres, Transaction};
+
+    use super::*;
+    use crate::tests::{at, durations, ids, pool};
+
+    /// Two groups, the user being in both of them, with courses around 9:00 to 12:00
+    async fn seed(tx: &mut Transaction<'_, Postgres>) -> (i64, i32, i32) {
+        let user = 999_999_001;
+        sqlx::query(
+            "insert into users (id, firstname, lastname, email, password)
+             values ($1, 'Test', 'Test', 'schedule-test@cyrel.invalid', '')",
+        )
+        .bind(user)
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+
+        let mut groups = Vec::new();
+        for name in ["schedule-test-1", "schedule-test-2"] {
+            let (id,): (i32,) = sqlx::query_as(
+                "insert into groups (name, private) values ($1, false) returning id",
+            )
+            .bind(name)
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+            sqlx::query("insert into users_groups (user_id, group_id) values ($1, $2)")
+                .bind(user)
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .unwrap();
+            groups.push(id);
+        }
+
+        let courses = [
+            ("test-before", at(7, 0), Some(at(9, 0)), None),
+            ("test-straddle-start", at(8, 0), Some(at(10, 0)), None),
+            ("test-enclosing", at(8, 0), Some(at(13, 0)), None),
+            ("test-inside", at(9, 30), Some(at(11, 0)), None),
+            ("test-straddle-end", at(11, 0), Some(at(13, 0)), None),
+            ("test-after", at(12, 0), Some(at(14, 0)), None),
+            ("test-open-default", at(7, 45), None, Some("TD")),
+            ("test-open-category", at(7, 15), None, Some("CM")),
+            ("test-open-inside", at(10, 0), None, None),
+        ];
+        for (id, start, end, category) in courses {
+            sqlx::query(
+                "insert into courses (id, start_time, end_time, category)
+                 values ($1, $2, $3, $4)",
+            )
+            .bind(id)
+            .bind(start)
+            .bind(end)
+            .bind(category)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+            sqlx::query("insert into groups_courses (group_id, course_id) values ($1, $2)")
+                .bind(groups[0])
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .unwrap();
+        }
+        sqlx::query("insert into groups_courses (group_id, course_id) values ($1, $2)")
+            .bind(groups[1])
+            .bind("test-inside")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        (user, groups[0], groups[1])
+    }
+
+    #[tokio::test]
+    async fn overlapping_courses() {
+        let pool = match pool("overlapping_courses").await {
+            Some(pool) => pool,
+            None => return,
+        };
+        let mut tx = pool.begin().await.unwrap();
+        let (_, group, _) = seed(&mut tx).await;
+
+        let courses = get(
+            &mut tx,
+            &durations(),
+            group,
+            at(9, 0),
+            at(12, 0),
+            Page::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            ids(&courses),
+            [
+                "test-open-category",
+                "test-open-default",
+                "test-enclosing",
+                "test-straddle-start",
+                "test-inside",
+                "test-open-inside",
+                "test-straddle-end",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn open_ended_courses() {
+        let pool = match pool("open_ended_courses").await {
+            Some(pool) => pool,
+            None => return,
+        };
+        let mut tx = pool.begin().await.unwrap();
+        let (_, group, _) = seed(&mut tx).await;
+
+        // Without the CM duration, the course starting at 7:15 ends at 8:45
+        let uncategorized = Durations {
+            default: 90,
+            categories: HashMap::new(),
+        };
+        let courses = get(
+            &mut tx,
+            &uncategorized,
+            group,
+            at(8, 50),
+            at(9, 20),
+            Page::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            ids(&courses),
+            [
+                "test-before",
+                "test-open-default",
+                "test-enclosing",
+                "test-straddle-start",
+            ]
+        );
+
+        let courses = get(
+            &mut tx,
+            &durations(),
+            group,
+            at(9, 10),
+            at(9, 20),
+            Page::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            ids(&courses),
+            [
+                "test-open-category",
+                "test-open-default",
+                "test-enclosing",
+                "test-straddle-start",
+            ]
+        );
+        // The end they are considered to have is returned
+        let ends: Vec<_> = courses.iter().map(|c| c.end_time).collect();
+        assert_eq!(ends, [at(9, 15), at(9, 15), at(13, 0), at(10, 0)]);
+    }
+
+    #[tokio::test]
+    async fn paginated_courses() {
+        let pool = match pool("paginated_courses").await {
+            Some(pool) => pool,
+            None => return,
+        };
+        let mut tx = pool.begin().await.unwrap();
+        let (_, group, _) = seed(&mut tx).await;
+
+        let page = Page {
+            limit: Some(3),
+            offset: 2,
+        };
+        let courses = get(&mut tx, &durations(), group, at(9, 0), at(12, 0), page)
+            .await
+            .unwrap();
+        assert_eq!(
+            ids(&courses),
+            ["test-enclosing", "test-straddle-start", "test-inside"]
+        );
+
+        let page = Page {
+            limit: Some(3),
+            offset: 6,
+        };
+        let courses = get(&mut tx, &durations(), group, at(9, 0), at(12, 0), page)
+            .await
+            .unwrap();
+        assert_eq!(ids(&courses), ["test-straddle-end"]);
+    }
+
+    #[tokio::test]
+    async fn user_courses() {
+        let pool = match pool("user_courses").await {
+            Some(pool) => pool,
+            None => return,
+        };
+        let mut tx = pool.begin().await.unwrap();
+        let (user, first, second) = seed(&mut tx).await;
+
+        let page = Page {
+            limit: Some(2),
+            offset: 3,
+        };
+        let courses = get_for_user(&mut tx, &durations(), user, at(9, 0), at(12, 0), page)
+            .await
+            .unwrap();
+        assert_eq!(
+            ids(courses.iter().map(|c| &c.course)),
+            ["test-straddle-start", "test-inside"]
+        );
+        assert_eq!(courses[0].groups, [first]);
+        assert_eq!(courses[1].groups, [first, second]);
+    }
+}
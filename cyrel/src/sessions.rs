@@ -0,0 +1,125 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::postgres::PgExecutor;
+use sqlx::PgPool;
+
+use crate::authentication::Claims;
+use crate::SETTINGS;
+
+/// What a client gets when logging in or refreshing its session
+#[derive(Serialize)]
+pub struct SessionTokens {
+    /// Short-lived JWT to put in the `Authorization` header
+    pub access: String,
+    /// Single-use token to get new [`SessionTokens`] with
+    pub refresh: String,
+}
+
+#[derive(Serialize)]
+pub struct Session {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub refreshed_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Whether this is the session used to make the request
+    pub current: bool,
+}
+
+fn tokens(user: i64, session: &str, refresh: String) -> anyhow::Result<SessionTokens> {
+    Ok(SessionTokens {
+        access: Claims::new(user, session).to_jwt(&SETTINGS.jwt.secret)?,
+        refresh,
+    })
+}
+
+/// Open a new session for `user`
+pub async fn open(pool: &PgPool, user: i64) -> anyhow::Result<SessionTokens> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let refresh = uuid::Uuid::new_v4().to_string();
+
+    sqlx::query!(
+        "insert into sessions (id, user_id, refresh_token, expires_at)
+         values ($1, $2, $3, now() + make_interval(secs => $4))",
+        id,
+        user,
+        refresh,
+        SETTINGS.jwt.refresh as f64,
+    )
+    .execute(pool)
+    .await?;
+
+    tokens(user, &id, refresh)
+}
+
+/// Exchange a refresh token for new tokens, extending the session.
+///
+/// The refresh token is rotated, so it can't be used again.
+pub async fn refresh(pool: &PgPool, refresh: &str) -> anyhow::Result<Option<SessionTokens>> {
+    let new_refresh = uuid::Uuid::new_v4().to_string();
+
+    match sqlx::query!(
+        "update sessions
+         set refresh_token = $2,
+             refreshed_at = now(),
+             expires_at = now() + make_interval(secs => $3)
+         where refresh_token = $1 and expires_at > now()
+         returning id, user_id",
+        refresh,
+        new_refresh,
+        SETTINGS.jwt.refresh as f64,
+    )
+    .fetch_optional(pool)
+    .await?
+    {
+        Some(s) => Ok(Some(tokens(s.user_id, &s.id, new_refresh)?)),
+        None => Ok(None),
+    }
+}
+
+/// Live sessions of `user`
+pub async fn list(pool: &PgPool, user: i64, current: &str) -> sqlx::Result<Vec<Session>> {
+    sqlx::query_as!(
+        Session,
+        r#"select id, created_at, refreshed_at, expires_at, id = $2 as "current!"
+           from sessions
+           where user_id = $1 and expires_at > now()
+           order by refreshed_at desc"#,
+        user,
+        current,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Close a session of `user`, returns whether it existed
+pub async fn close(pool: &PgPool, user: i64, session: &str) -> sqlx::Result<bool> {
+    Ok(sqlx::query!(
+        "delete from sessions where user_id = $1 and id = $2",
+        user,
+        session,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected()
+        > 0)
+}
+
+/// Close every session of `user`
+pub async fn close_all<'c>(executor: impl PgExecutor<'c>, user: i64) -> sqlx::Result<u64> {
+    Ok(
+        sqlx::query!("delete from sessions where user_id = $1", user)
+            .execute(executor)
+            .await?
+            .rows_affected(),
+    )
+}
+
+/// Delete every expired session.
+pub async fn purge<'c>(executor: impl PgExecutor<'c>) -> sqlx::Result<u64> {
+    Ok(
+        sqlx::query!("delete from sessions where expires_at <= now()")
+            .execute(executor)
+            .await?
+            .rows_affected(),
+    )
+}
@@ -1,16 +1,39 @@
+use std::collections::HashMap;
+
+use chrono_tz::Tz;
 use clap::ArgMatches;
 use config::{Config, ConfigError, Environment, File};
 use serde::Deserialize;
 
+/// Lifetimes are in seconds
 #[derive(Debug, Deserialize)]
-pub struct Celcat {
-    pub username: String,
-    pub password: String,
+pub struct Jwt {
+    pub secret: String,
+    /// Lifetime of an access token
+    pub access: i64,
+    /// Lifetime of a session without refreshing it
+    pub refresh: i64,
 }
 
+/// Argon2 cost parameters
 #[derive(Debug, Deserialize)]
-pub struct Jwt {
-    pub secret: String,
+pub struct Hashing {
+    /// Memory size, in KiB
+    pub memory: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+/// Rules passwords must follow
+#[derive(Debug, Deserialize)]
+pub struct Password {
+    /// Minimum number of characters
+    pub length: usize,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digit: bool,
+    /// Whether a character other than a letter or a digit is required
+    pub symbol: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -26,12 +49,73 @@ pub struct Smtp {
     pub server: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct Notifications {
+    /// Delay between two digests, in seconds
+    pub interval: u64,
+}
+
+/// Durations given to the courses without an end, in minutes
+#[derive(Debug, Deserialize)]
+pub struct Durations {
+    pub default: i32,
+    /// By category, overriding `default`
+    pub categories: HashMap<String, i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Schedule {
+    /// Where the campus is, naive times are in its local time
+    pub timezone: Tz,
+    pub durations: Durations,
+    /// Maximum number of courses returned at once
+    pub limit: i64,
+}
+
+/// Durations are in seconds
+#[derive(Debug, Deserialize)]
+pub struct Lockout {
+    /// Failed logins allowed before locking the account
+    pub after: u32,
+    /// First lockout, doubled with each new failure
+    pub base: u64,
+    /// Longest lockout
+    pub max: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Limits {
+    /// Length of the window requests are counted in, in seconds
+    pub window: u64,
+    /// Sensitive requests allowed from an IP address during a window
+    pub ip: u32,
+    /// Emails sent to an address during a window
+    pub email: u32,
+    /// Whether cyrel is behind a reverse proxy, whose `X-Forwarded-For` header can be trusted
+    pub proxy: bool,
+    pub lockout: Lockout,
+}
+
+/// Lifetimes are in seconds
+#[derive(Debug, Deserialize)]
+pub struct Tokens {
+    pub registration: i64,
+    pub reset: i64,
+    /// Delay between two purges of expired or used tokens
+    pub purge: u64,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub jwt: Jwt,
+    pub hashing: Hashing,
+    pub password: Password,
     pub database: Database,
     pub smtp: Smtp,
-    pub celcat: Celcat,
+    pub tokens: Tokens,
+    pub notifications: Notifications,
+    pub limits: Limits,
+    pub schedule: Schedule,
     pub port: u16,
 }
 
@@ -39,6 +123,35 @@ impl Settings {
     pub fn new(matches: &ArgMatches) -> Result<Self, ConfigError> {
         let mut s = Config::default();
 
+        s.set_default("jwt.access", 15 * 60)?;
+        s.set_default("jwt.refresh", 14 * 24 * 60 * 60)?;
+        s.set_default("hashing.memory", 19 * 1024)?;
+        s.set_default("hashing.iterations", 2)?;
+        s.set_default("hashing.parallelism", 1)?;
+        s.set_default("password.length", 10)?;
+        s.set_default("password.lowercase", true)?;
+        s.set_default("password.uppercase", true)?;
+        s.set_default("password.digit", true)?;
+        s.set_default("password.symbol", false)?;
+        s.set_default("tokens.registration", 24 * 60 * 60)?;
+        s.set_default("tokens.reset", 60 * 60)?;
+        s.set_default("tokens.purge", 60 * 60)?;
+        s.set_default("notifications.interval", 15 * 60)?;
+        s.set_default("limits.window", 10 * 60)?;
+        s.set_default("limits.ip", 30)?;
+        s.set_default("limits.email", 3)?;
+        s.set_default("limits.proxy", false)?;
+        s.set_default("limits.lockout.after", 5)?;
+        s.set_default("limits.lockout.base", 60)?;
+        s.set_default("limits.lockout.max", 24 * 60 * 60)?;
+        s.set_default("schedule.timezone", "Europe/Paris")?;
+        s.set_default("schedule.durations.default", 90)?;
+        s.set_default(
+            "schedule.durations.categories",
+            HashMap::<String, i64>::new(),
+        )?;
+        s.set_default("schedule.limit", 1000)?;
+
         if let Some(f) = matches.value_of("CONFIG") {
             s.merge(File::with_name(f))?;
         }
@@ -48,6 +161,17 @@ impl Settings {
             s.set("port", p)?;
         }
 
-        s.try_into()
+        let settings: Self = s.try_into()?;
+        if settings.tokens.purge == 0 {
+            return Err(ConfigError::Message(
+                "tokens.purge must be a positive number of seconds".to_owned(),
+            ));
+        }
+        if settings.notifications.interval == 0 {
+            return Err(ConfigError::Message(
+                "notifications.interval must be a positive number of seconds".to_owned(),
+            ));
+        }
+        Ok(settings)
     }
 }
@@ -0,0 +1,89 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+
+/// Last run of a job of `cyrel-sync`
+#[derive(Serialize)]
+pub struct Run {
+    /// `courses`, `near_courses` or `students`
+    pub job: String,
+    /// `running`, `succeeded`, `partial` or `failed`
+    pub status: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    /// Only given to the users managing groups
+    pub error: Option<String>,
+}
+
+/// A group whose courses couldn't all be synced by the last run
+#[derive(Serialize)]
+pub struct FailingGroup {
+    pub id: i32,
+    pub name: String,
+    /// `partial` or `failed`
+    pub outcome: String,
+    pub error: String,
+}
+
+#[derive(Serialize)]
+pub struct Status {
+    /// End of the last run syncing courses, even if some groups failed
+    pub courses_updated_at: Option<DateTime<Utc>>,
+    pub students_updated_at: Option<DateTime<Utc>>,
+    pub runs: Vec<Run>,
+    /// Only given to the users managing groups
+    pub failing_groups: Option<Vec<FailingGroup>>,
+}
+
+/// Errors and failing groups are only given to `admin`s, the users managing groups
+pub async fn status(pool: &PgPool, admin: bool) -> sqlx::Result<Status> {
+    let updated_at = sqlx::query!(
+        "select max(ended_at) filter (where job in ('courses', 'near_courses')) as courses,
+                max(ended_at) filter (where job = 'students') as students
+         from sync_runs
+         where status in ('succeeded', 'partial')",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let mut runs = sqlx::query_as!(
+        Run,
+        "select distinct on (job) job, status, started_at, ended_at, error from sync_runs
+         order by job, started_at desc",
+    )
+    .fetch_all(pool)
+    .await?;
+    if !admin {
+        for run in &mut runs {
+            run.error = None;
+        }
+    }
+
+    let failing_groups = if admin {
+        Some(
+            sqlx::query_as!(
+                FailingGroup,
+                "select g.id, g.name, r.outcome, r.error from sync_runs_groups as r
+                 join groups as g on g.id = r.group_id
+                 where r.run_id = (
+                     select id from sync_runs
+                     where job in ('courses', 'near_courses') and ended_at is not null
+                     order by started_at desc
+                     limit 1
+                 )
+                 order by g.name",
+            )
+            .fetch_all(pool)
+            .await?,
+        )
+    } else {
+        None
+    };
+
+    Ok(Status {
+        courses_updated_at: updated_at.courses,
+        students_updated_at: updated_at.students,
+        runs,
+        failing_groups,
+    })
+}
@@ -0,0 +1,143 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{PgExecutor, PgPool};
+
+use crate::schedule::Course;
+use crate::settings::Durations;
+
+#[derive(Debug, Serialize)]
+pub struct Teacher {
+    pub id: i32,
+    pub name: String,
+}
+
+/// Teachers whose name contains `query`, ignoring case
+pub async fn search(pool: &PgPool, query: &str) -> sqlx::Result<Vec<Teacher>> {
+    sqlx::query_as!(
+        Teacher,
+        "select id, name from teachers
+         where strpos(lower(name), lower($1)) > 0
+         order by name
+         limit 50",
+        query,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn exists(pool: &PgPool, teacher: i32) -> sqlx::Result<bool> {
+    Ok(
+        sqlx::query!("select id from teachers where id = $1", teacher)
+            .fetch_optional(pool)
+            .await?
+            .is_some(),
+    )
+}
+
+/// Courses given by `teacher` overlapping `start` to `end`, among those of the groups `user` is
+/// allowed to see
+pub async fn schedule<'c>(
+    executor: impl PgExecutor<'c>,
+    durations: &Durations,
+    user: i64,
+    teacher: i32,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> sqlx::Result<Vec<Course>> {
+    sqlx::query_as!(
+        Course,
+        r#"select c.id, c.start_time, course_end(c, $5, $6) as "end_time!", c.category,
+                  c.module, c.room, c.teacher, c.description
+           from courses as c
+           join courses_teachers as ct on c.id = ct.course_id
+           where ct.teacher_id = $2 and c.start_time < $4
+             and course_end(c, $5, $6) > $3
+             and can_see_course($1, c.id)
+           order by c.start_time, c.id"#,
+        user,
+        teacher,
+        start,
+        end,
+        json!(durations.categories),
+        durations.default,
+    )
+    .fetch_all(executor)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{at, durations, ids, pool};
+
+    #[tokio::test]
+    async fn private_group_courses() {
+        let pool = match pool("private_group_courses").await {
+            Some(pool) => pool,
+            None => return,
+        };
+        let mut tx = pool.begin().await.unwrap();
+
+        let user = 999_999_002;
+        sqlx::query(
+            "insert into users (id, firstname, lastname, email, password)
+             values ($1, 'Test', 'Test', 'teachers-test@cyrel.invalid', '')",
+        )
+        .bind(user)
+        .execute(&mut tx)
+        .await
+        .unwrap();
+        let (teacher,): (i32,) =
+            sqlx::query_as("insert into teachers (name) values ('Teacher Test') returning id")
+                .fetch_one(&mut tx)
+                .await
+                .unwrap();
+
+        // The user is only in the public group
+        for (course, name, private, member) in [
+            ("test-public", "teachers-test-public", false, true),
+            ("test-private", "teachers-test-private", true, false),
+        ] {
+            let (group,): (i32,) =
+                sqlx::query_as("insert into groups (name, private) values ($1, $2) returning id")
+                    .bind(name)
+                    .bind(private)
+                    .fetch_one(&mut tx)
+                    .await
+                    .unwrap();
+            if member {
+                sqlx::query("insert into users_groups (user_id, group_id) values ($1, $2)")
+                    .bind(user)
+                    .bind(group)
+                    .execute(&mut tx)
+                    .await
+                    .unwrap();
+            }
+            sqlx::query("insert into courses (id, start_time, end_time) values ($1, $2, $3)")
+                .bind(course)
+                .bind(at(9, 0))
+                .bind(at(10, 0))
+                .execute(&mut tx)
+                .await
+                .unwrap();
+            sqlx::query("insert into groups_courses (group_id, course_id) values ($1, $2)")
+                .bind(group)
+                .bind(course)
+                .execute(&mut tx)
+                .await
+                .unwrap();
+            sqlx::query("insert into courses_teachers (course_id, teacher_id) values ($1, $2)")
+                .bind(course)
+                .bind(teacher)
+                .execute(&mut tx)
+                .await
+                .unwrap();
+        }
+
+        let courses = schedule(&mut tx, &durations(), user, teacher, at(8, 0), at(12, 0))
+            .await
+            .unwrap();
+        assert_eq!(ids(&courses), ["test-public"]);
+    }
+}
@@ -0,0 +1,56 @@
+//! Helpers shared by the tests
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use chrono::{DateTime, TimeZone, Utc};
+use sqlx::postgres::PgConnectOptions;
+use sqlx::PgPool;
+
+use crate::schedule::Course;
+use crate::settings::Durations;
+
+/// Create an empty database for the test `name` and migrate it.
+///
+/// It is created on the server of `DATABASE_URL`, the tests are skipped if it isn't set.
+pub async fn pool(name: &str) -> Option<PgPool> {
+    dotenv::dotenv().ok();
+    let url = match std::env::var("DATABASE_URL") {
+        Ok(url) => url,
+        Err(_) => {
+            eprintln!("DATABASE_URL isn't set, skipping");
+            return None;
+        }
+    };
+
+    let name = format!("cyrel_test_{}", name);
+    let server = PgPool::connect(&url).await.unwrap();
+    sqlx::query(&format!("drop database if exists {}", name))
+        .execute(&server)
+        .await
+        .unwrap();
+    sqlx::query(&format!("create database {}", name))
+        .execute(&server)
+        .await
+        .unwrap();
+
+    let options = PgConnectOptions::from_str(&url).unwrap().database(&name);
+    let pool = PgPool::connect_with(options).await.unwrap();
+    cyrel_common::schema::MIGRATOR.run(&pool).await.unwrap();
+    Some(pool)
+}
+
+pub fn at(hour: u32, min: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2022, 5, 2, hour, min, 0).unwrap()
+}
+
+pub fn durations() -> Durations {
+    Durations {
+        default: 90,
+        categories: HashMap::from([("CM".to_owned(), 120)]),
+    }
+}
+
+pub fn ids<'a>(courses: impl IntoIterator<Item = &'a Course>) -> Vec<&'a str> {
+    courses.into_iter().map(|c| c.id.as_str()).collect()
+}
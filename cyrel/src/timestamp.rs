@@ -0,0 +1,22 @@
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use cyrel_common::time::localize;
+use serde::Deserialize;
+
+/// A time given by a client, with an offset or in the local time of the campus
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged)]
+pub enum Timestamp {
+    /// RFC 3339
+    Aware(DateTime<FixedOffset>),
+    Naive(NaiveDateTime),
+}
+
+impl Timestamp {
+    pub fn resolve(self, tz: Tz) -> anyhow::Result<DateTime<Utc>> {
+        match self {
+            Timestamp::Aware(time) => Ok(time.with_timezone(&Utc)),
+            Timestamp::Naive(time) => localize(tz, time),
+        }
+    }
+}
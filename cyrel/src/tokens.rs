@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use sqlx::postgres::PgExecutor;
+use sqlx::PgPool;
+use tracing::{error, info};
+
+use crate::models::User;
+use crate::sessions;
+use crate::SETTINGS;
+
+/// What a token can be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    Registration,
+    Reset,
+}
+
+impl Purpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            Purpose::Registration => "registration",
+            Purpose::Reset => "reset",
+        }
+    }
+
+    /// Lifetime of a token, in seconds
+    fn ttl(self) -> i64 {
+        match self {
+            Purpose::Registration => SETTINGS.tokens.registration,
+            Purpose::Reset => SETTINGS.tokens.reset,
+        }
+    }
+}
+
+/// Create a new token for `user`, valid for the lifetime configured for `purpose`.
+///
+/// The password of `user` is not stored.
+pub async fn create<'c>(
+    executor: impl PgExecutor<'c>,
+    purpose: Purpose,
+    user: &User,
+) -> sqlx::Result<String> {
+    let token = uuid::Uuid::new_v4().to_string();
+
+    sqlx::query!(
+        "insert into tokens (token, purpose, user_id, firstname, lastname, email, ttl)
+         values ($1, $2, $3, $4, $5, $6, make_interval(secs => $7))",
+        token,
+        purpose.as_str(),
+        user.id,
+        user.firstname,
+        user.lastname,
+        user.email,
+        purpose.ttl() as f64,
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(token)
+}
+
+/// Get the user associated with a valid token, without using it.
+pub async fn peek<'c>(
+    executor: impl PgExecutor<'c>,
+    purpose: Purpose,
+    token: &str,
+) -> sqlx::Result<Option<User>> {
+    Ok(sqlx::query!(
+        "select user_id, firstname, lastname, email from tokens
+         where token = $1 and purpose = $2
+           and used_at is null and created_at + ttl > now()",
+        token,
+        purpose.as_str(),
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(|t| User {
+        id: t.user_id,
+        firstname: t.firstname,
+        lastname: t.lastname,
+        email: t.email,
+        password: "".to_string(),
+        role: None,
+        disabled: false,
+    }))
+}
+
+/// Mark a valid token as used, and get the user associated with it.
+///
+/// A token can only be used once: concurrent calls with the same token
+/// will only return the user once.
+pub async fn consume<'c>(
+    executor: impl PgExecutor<'c>,
+    purpose: Purpose,
+    token: &str,
+) -> sqlx::Result<Option<User>> {
+    Ok(sqlx::query!(
+        "update tokens set used_at = now()
+         where token = $1 and purpose = $2
+           and used_at is null and created_at + ttl > now()
+         returning user_id, firstname, lastname, email",
+        token,
+        purpose.as_str(),
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(|t| User {
+        id: t.user_id,
+        firstname: t.firstname,
+        lastname: t.lastname,
+        email: t.email,
+        password: "".to_string(),
+        role: None,
+        disabled: false,
+    }))
+}
+
+/// The first characters of a token, enough to tell tokens apart in the logs without leaking them
+pub fn redact(token: &str) -> String {
+    let prefix: String = token.chars().take(6).collect();
+    format!("{}…", prefix)
+}
+
+/// Delete every used or expired token.
+pub async fn purge<'c>(executor: impl PgExecutor<'c>) -> sqlx::Result<u64> {
+    Ok(sqlx::query!(
+        "delete from tokens
+         where used_at is not null or created_at + ttl <= now()"
+    )
+    .execute(executor)
+    .await?
+    .rows_affected())
+}
+
+/// Purge tokens and expired sessions forever, every `SETTINGS.tokens.purge` seconds.
+pub async fn purge_periodically(pool: PgPool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(SETTINGS.tokens.purge));
+    loop {
+        interval.tick().await;
+        match purge(&pool).await {
+            Ok(n) => info!("purged {} tokens", n),
+            Err(err) => error!("failed to purge tokens: {}", err),
+        }
+        match sessions::purge(&pool).await {
+            Ok(n) => info!("purged {} sessions", n),
+            Err(err) => error!("failed to purge sessions: {}", err),
+        }
+    }
+}
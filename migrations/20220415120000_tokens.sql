@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS tokens
+(
+    token      TEXT PRIMARY KEY,
+    purpose    TEXT                     NOT NULL CHECK (purpose IN ('registration', 'reset')),
+    user_id    BIGINT                   NOT NULL,
+    firstname  TEXT                     NOT NULL,
+    lastname   TEXT                     NOT NULL,
+    email      TEXT                     NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    ttl        INTERVAL                 NOT NULL,
+    used_at    TIMESTAMP WITH TIME ZONE
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS calendar_tokens
+(
+    token   TEXT PRIMARY KEY,
+    user_id BIGINT REFERENCES users NOT NULL UNIQUE
+);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS sessions
+(
+    id            TEXT PRIMARY KEY,
+    user_id       BIGINT REFERENCES users  NOT NULL,
+    refresh_token TEXT                     NOT NULL UNIQUE,
+    created_at    TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    refreshed_at  TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    expires_at    TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS courses_changes
+(
+    id             SERIAL PRIMARY KEY,
+    course_id      TEXT REFERENCES courses     NOT NULL,
+    group_id       INTEGER REFERENCES groups,
+    kind           TEXT                        NOT NULL CHECK (kind IN ('inserted', 'cancelled', 'moved', 'updated')),
+    old_start_time TIMESTAMP WITHOUT TIME ZONE,
+    old_end_time   TIMESTAMP WITHOUT TIME ZONE,
+    old_room       TEXT,
+    new_start_time TIMESTAMP WITHOUT TIME ZONE,
+    new_end_time   TIMESTAMP WITHOUT TIME ZONE,
+    new_room       TEXT,
+    detected_at    TIMESTAMP WITH TIME ZONE    NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS courses_changes_detected_at ON courses_changes (detected_at);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS notifications_preferences
+(
+    user_id BIGINT PRIMARY KEY REFERENCES users,
+    enabled BOOLEAN NOT NULL DEFAULT false,
+    days    INTEGER NOT NULL DEFAULT 7
+);
+
+ALTER TABLE courses_changes
+ADD COLUMN notified_at TIMESTAMP WITH TIME ZONE;
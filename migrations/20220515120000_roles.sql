@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS roles
+(
+    name TEXT PRIMARY KEY
+);
+
+CREATE TABLE IF NOT EXISTS roles_permissions
+(
+    role       TEXT REFERENCES roles NOT NULL,
+    permission TEXT                  NOT NULL CHECK (permission IN ('groups', 'departments', 'clients', 'users')),
+    UNIQUE (role, permission)
+);
+
+INSERT INTO roles (name)
+VALUES ('admin')
+ON CONFLICT DO NOTHING;
+
+INSERT INTO roles_permissions (role, permission)
+VALUES ('admin', 'groups'),
+       ('admin', 'departments'),
+       ('admin', 'clients'),
+       ('admin', 'users')
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS role     TEXT REFERENCES roles,
+    ADD COLUMN IF NOT EXISTS disabled BOOLEAN NOT NULL DEFAULT false;
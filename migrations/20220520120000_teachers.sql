@@ -0,0 +1,28 @@
+CREATE TABLE IF NOT EXISTS teachers
+(
+    id   SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS courses_teachers
+(
+    course_id  TEXT REFERENCES courses     NOT NULL,
+    teacher_id INTEGER REFERENCES teachers NOT NULL,
+    UNIQUE (course_id, teacher_id)
+);
+
+CREATE INDEX IF NOT EXISTS courses_teachers_teacher_id ON courses_teachers (teacher_id);
+
+INSERT INTO teachers (name)
+SELECT DISTINCT trim(t)
+FROM courses,
+     regexp_split_to_table(teacher, '[,;/]') AS t
+WHERE trim(t) <> ''
+ON CONFLICT DO NOTHING;
+
+INSERT INTO courses_teachers (course_id, teacher_id)
+SELECT DISTINCT c.id, t.id
+FROM courses AS c,
+     regexp_split_to_table(c.teacher, '[,;/]') AS n
+         JOIN teachers AS t ON t.name = trim(n)
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,28 @@
+CREATE TABLE IF NOT EXISTS rooms
+(
+    id   SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS courses_rooms
+(
+    course_id TEXT REFERENCES courses  NOT NULL,
+    room_id   INTEGER REFERENCES rooms NOT NULL,
+    UNIQUE (course_id, room_id)
+);
+
+CREATE INDEX IF NOT EXISTS courses_rooms_room_id ON courses_rooms (room_id);
+
+INSERT INTO rooms (name)
+SELECT DISTINCT trim(r)
+FROM courses,
+     regexp_split_to_table(room, ',') AS r
+WHERE trim(r) <> ''
+ON CONFLICT DO NOTHING;
+
+INSERT INTO courses_rooms (course_id, room_id)
+SELECT DISTINCT c.id, r.id
+FROM courses AS c,
+     regexp_split_to_table(c.room, ',') AS n
+         JOIN rooms AS r ON r.name = trim(n)
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,10 @@
+-- Celcat times were stored in the local time of the campus
+ALTER TABLE courses
+    ALTER COLUMN start_time TYPE TIMESTAMP WITH TIME ZONE USING start_time AT TIME ZONE 'Europe/Paris',
+    ALTER COLUMN end_time TYPE TIMESTAMP WITH TIME ZONE USING end_time AT TIME ZONE 'Europe/Paris';
+
+ALTER TABLE courses_changes
+    ALTER COLUMN old_start_time TYPE TIMESTAMP WITH TIME ZONE USING old_start_time AT TIME ZONE 'Europe/Paris',
+    ALTER COLUMN old_end_time TYPE TIMESTAMP WITH TIME ZONE USING old_end_time AT TIME ZONE 'Europe/Paris',
+    ALTER COLUMN new_start_time TYPE TIMESTAMP WITH TIME ZONE USING new_start_time AT TIME ZONE 'Europe/Paris',
+    ALTER COLUMN new_end_time TYPE TIMESTAMP WITH TIME ZONE USING new_end_time AT TIME ZONE 'Europe/Paris';
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS courses_fingerprints
+(
+    course_id  TEXT PRIMARY KEY REFERENCES courses,
+    calendar   BYTEA                    NOT NULL,
+    event      BYTEA                    NOT NULL,
+    fetched_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS sync_runs
+(
+    id         SERIAL PRIMARY KEY,
+    job        TEXT                     NOT NULL CHECK (job IN ('courses', 'near_courses', 'students')),
+    status     TEXT                     NOT NULL CHECK (status IN ('running', 'succeeded', 'partial', 'failed')),
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    ended_at   TIMESTAMP WITH TIME ZONE,
+    counts     JSONB,
+    error      TEXT
+);
+
+CREATE INDEX IF NOT EXISTS sync_runs_job_started_at ON sync_runs (job, started_at);
+
+CREATE TABLE IF NOT EXISTS sync_runs_groups
+(
+    run_id   INTEGER REFERENCES sync_runs ON DELETE CASCADE NOT NULL,
+    group_id INTEGER REFERENCES groups ON DELETE CASCADE    NOT NULL,
+    outcome  TEXT                                           NOT NULL CHECK (outcome IN ('partial', 'failed')),
+    error    TEXT                                           NOT NULL,
+    UNIQUE (run_id, group_id)
+);
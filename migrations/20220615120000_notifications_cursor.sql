@@ -0,0 +1,8 @@
+ALTER TABLE notifications_preferences
+ADD COLUMN IF NOT EXISTS notified_up_to INTEGER NOT NULL DEFAULT 0;
+
+UPDATE notifications_preferences
+SET notified_up_to = coalesce((SELECT max(id) FROM courses_changes WHERE notified_at IS NOT NULL), 0);
+
+ALTER TABLE courses_changes
+DROP COLUMN IF EXISTS notified_at;
@@ -0,0 +1,25 @@
+-- A user can see the groups they are in, and their siblings
+CREATE OR REPLACE FUNCTION can_see_group(user_id BIGINT, group_id INTEGER) RETURNS BOOLEAN
+    LANGUAGE sql
+    STABLE
+AS
+$$
+SELECT EXISTS(SELECT
+              FROM groups AS g
+                       JOIN groups AS h ON h.id = g.id OR h.parent = g.parent
+                       JOIN users_groups AS ug ON ug.group_id = h.id
+              WHERE ug.user_id = $1
+                AND g.id = $2)
+$$;
+
+-- A user can see the courses of the groups they can see
+CREATE OR REPLACE FUNCTION can_see_course(user_id BIGINT, course_id TEXT) RETURNS BOOLEAN
+    LANGUAGE sql
+    STABLE
+AS
+$$
+SELECT EXISTS(SELECT
+              FROM groups_courses AS gc
+              WHERE gc.course_id = $2
+                AND can_see_group($1, gc.group_id))
+$$;
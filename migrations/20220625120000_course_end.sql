@@ -0,0 +1,10 @@
+-- Courses without an end last the duration of their category in `durations`, in minutes, or `default_duration`
+CREATE OR REPLACE FUNCTION course_end(c courses, durations JSONB, default_duration INTEGER)
+    RETURNS TIMESTAMP WITH TIME ZONE
+    LANGUAGE sql
+    STABLE
+AS
+$$
+SELECT coalesce(c.end_time,
+                c.start_time + make_interval(mins => coalesce((durations ->> c.category)::int, default_duration)))
+$$;